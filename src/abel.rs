@@ -0,0 +1,391 @@
+//
+// abel.rs: ABEL-HDL input parser
+//
+// Read the equation subset of ABEL's .abl format, and convert it to
+// the same 'Content' structure as the galasm-style parser produces.
+// Supported are 'MODULE', 'TITLE', a 'DEVICE' declaration, 'PIN'
+// declarations and an 'EQUATIONS' section. Truth tables, state
+// diagrams, sets and so on are not.
+//
+// ABEL's statements and equations are close enough to CUPL's that we
+// reuse the CUPL lexer and equation parser, after mapping across the
+// assignment operators and extensions.
+//
+
+use std::{collections::HashMap, iter::Peekable};
+
+use crate::{
+    chips::Chip,
+    cupl::{self, Lexeme, Statement},
+    errors::{at_line, Error, ErrorCode, LineNum},
+    parser::{self, Content, Notes, PinDecl, Symbols},
+};
+
+// Strip comments, which run from '"' to the next '"' or end of line,
+// or from '//' to the end of line. Returns the remaining characters
+// with their line numbers.
+fn strip_comments(data: &str) -> Vec<(LineNum, char)> {
+    let mut res = Vec::new();
+    for (line_num, line) in (1..).zip(data.lines()) {
+        let mut chars = line.chars().peekable();
+        let mut in_comment = false;
+        while let Some(c) = chars.next() {
+            if c == '"' {
+                in_comment = !in_comment;
+            } else if !in_comment {
+                if c == '/' && chars.peek() == Some(&'/') {
+                    break;
+                }
+                res.push((line_num, c));
+            }
+        }
+        res.push((line_num, '\n'));
+    }
+    res
+}
+
+fn is_name(lexeme: &Lexeme, keyword: &str) -> bool {
+    matches!(lexeme, Lexeme::Name(name) if name.eq_ignore_ascii_case(keyword))
+}
+
+// Strings are lexed like CUPL's based numbers, quotes and all.
+fn unquote(s: &str) -> &str {
+    s.trim_matches('\'')
+}
+
+// Parse a comma-separated list of items.
+fn parse_list<I, T, F>(tokens: &mut Peekable<I>, mut item: F) -> Result<Vec<T>, Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+    F: FnMut(&mut Peekable<I>) -> Result<T, Error>,
+{
+    let mut res = vec![item(tokens)?];
+    while let Some((_, Lexeme::Punct(','))) = tokens.peek() {
+        tokens.next();
+        res.push(item(tokens)?);
+    }
+    Ok(res)
+}
+
+// Parse 'names PIN numbers [ISTYPE 'attrs']'.
+fn parse_pin_decl(statement: Statement) -> Result<PinDecl, Error> {
+    let line_num = statement[0].0;
+    let end_line = cupl::last_line(&statement);
+    let mut tokens = statement.into_iter().peekable();
+
+    let names = parse_list(&mut tokens, |tokens| {
+        let neg = matches!(tokens.peek(), Some((_, Lexeme::Punct('!'))));
+        if neg {
+            tokens.next();
+        }
+        match tokens.next() {
+            Some((_, Lexeme::Name(name))) => Ok((name, neg)),
+            Some((line_num, _)) => parser::err(line_num, ErrorCode::BadToken { expected: "pin" }),
+            None => parser::err(end_line, ErrorCode::BadToken { expected: "pin" }),
+        }
+    })?;
+
+    match tokens.next() {
+        Some((_, lexeme)) if is_name(&lexeme, "PIN") => (),
+        Some((line_num, _)) => {
+            return parser::err(line_num, ErrorCode::BadToken { expected: "PIN" })
+        }
+        None => return parser::err(end_line, ErrorCode::BadToken { expected: "PIN" }),
+    }
+
+    let pin_nums = parse_list(&mut tokens, |tokens| match tokens.next() {
+        Some((line_num, Lexeme::Number(num))) => num
+            .parse()
+            .or_else(|_| parser::err(line_num, ErrorCode::BadNumber { num: num.clone() })),
+        Some((line_num, _)) => parser::err(line_num, ErrorCode::BadToken { expected: "number" }),
+        None => parser::err(end_line, ErrorCode::BadToken { expected: "number" }),
+    })?;
+
+    // We work out the output types from the equations, so ignore
+    // ISTYPE.
+    if let Some((_, lexeme)) = tokens.peek() {
+        if is_name(lexeme, "ISTYPE") {
+            tokens.next();
+            tokens.next();
+        }
+    }
+    if let Some((line_num, _)) = tokens.next() {
+        return parser::err(line_num, ErrorCode::BadToken { expected: "';'" });
+    }
+
+    Ok((line_num, pin_nums, names))
+}
+
+// Rewrite an ABEL equation as a CUPL one: ':=' assigns the register,
+// the extensions are renamed, and feedback extensions on the
+// right-hand side are dropped.
+fn to_cupl_equation(chip: Chip, statement: Statement) -> Statement {
+    let mut res = Vec::new();
+    let mut tokens = statement.into_iter().peekable();
+    let mut in_rhs = false;
+    let mut has_ext = false;
+    while let Some((line_num, lexeme)) = tokens.next() {
+        match lexeme {
+            Lexeme::Punct(':') if !in_rhs => {
+                if !has_ext {
+                    res.push((line_num, Lexeme::Punct('.')));
+                    res.push((line_num, Lexeme::Name("d".to_string())));
+                }
+                in_rhs = true;
+            }
+            Lexeme::Punct('=') => {
+                in_rhs = true;
+                res.push((line_num, lexeme));
+            }
+            Lexeme::Punct('.') => {
+                let ext = match tokens.peek() {
+                    Some((_, Lexeme::Name(ext))) => ext.to_ascii_uppercase(),
+                    _ => {
+                        res.push((line_num, lexeme));
+                        continue;
+                    }
+                };
+                if in_rhs && ["FB", "Q", "PIN"].contains(&ext.as_str()) {
+                    tokens.next();
+                    continue;
+                }
+                has_ext = true;
+                let ext = match ext.as_str() {
+                    "D" => "d",
+                    "OE" => "oe",
+                    "CLK" => "ck",
+                    "AR" => "ar",
+                    "AP" if chip.family() == Chip::GAL20RA10 => "sp",
+                    "SP" if chip.family() == Chip::GAL22V10 => "sp",
+                    _ => {
+                        res.push((line_num, lexeme));
+                        continue;
+                    }
+                };
+                tokens.next();
+                res.push((line_num, lexeme));
+                res.push((line_num, Lexeme::Name(ext.to_string())));
+            }
+            _ => res.push((line_num, lexeme)),
+        }
+    }
+    res
+}
+
+// Parse everything up to the equations, returning the chip, the
+// signature, the pins, and the equation statements. Errors here stop
+// the parse.
+#[allow(clippy::type_complexity)]
+fn parse_declarations(data: &str) -> Result<(Chip, Vec<u8>, Vec<PinDecl>, Vec<Statement>), Error> {
+    let chars = strip_comments(data);
+    let last_line = data.lines().count();
+
+    let mut sig = Vec::new();
+    let mut chip = None;
+    let mut pin_decls = Vec::new();
+    let mut eqn_statements = Vec::new();
+    let mut in_equations = false;
+    'statements: for chars in chars.split(|(_, c)| *c == ';') {
+        let mut statement = cupl::lex(chars)?;
+
+        // Section keywords aren't followed by ';', so strip them off
+        // the front of the statement.
+        loop {
+            match statement.first() {
+                Some((_, lexeme)) if is_name(lexeme, "MODULE") => {
+                    if let Some((_, Lexeme::Name(name))) = statement.get(1) {
+                        sig = name.bytes().take(8).collect();
+                    }
+                    statement.drain(..usize::min(2, statement.len()));
+                }
+                Some((_, lexeme)) if is_name(lexeme, "TITLE") => {
+                    statement.drain(..usize::min(2, statement.len()));
+                }
+                Some((_, lexeme)) if is_name(lexeme, "DECLARATIONS") => {
+                    statement.remove(0);
+                }
+                Some((_, lexeme)) if is_name(lexeme, "EQUATIONS") => {
+                    statement.remove(0);
+                    in_equations = true;
+                }
+                Some((_, lexeme)) if is_name(lexeme, "END") => break 'statements,
+                _ => break,
+            }
+        }
+
+        if statement.is_empty() {
+            continue;
+        }
+        if in_equations {
+            eqn_statements.push(statement);
+        } else if statement.len() == 3 && is_name(&statement[1].1, "DEVICE") {
+            let (line_num, device) = match &statement[2] {
+                (line_num, Lexeme::Number(device)) => (*line_num, unquote(device)),
+                (line_num, _) => {
+                    return parser::err(*line_num, ErrorCode::BadToken { expected: "device" })
+                }
+            };
+            chip = Some(at_line(line_num, Chip::from_pal_name(device))?);
+        } else if statement.iter().any(|(_, lexeme)| is_name(lexeme, "PIN")) {
+            pin_decls.push(parse_pin_decl(statement)?);
+        } else {
+            return parser::err(
+                statement[0].0,
+                ErrorCode::BadToken {
+                    expected: "DEVICE or PIN declaration",
+                },
+            );
+        }
+    }
+
+    match chip {
+        Some(chip) => Ok((chip, sig, pin_decls, eqn_statements)),
+        None => parser::err(last_line, ErrorCode::NoDevice),
+    }
+}
+
+pub fn parse_str(data: &str) -> Result<Content, Vec<Error>> {
+    let (chip, sig, pin_decls, eqn_statements) = parse_declarations(data).map_err(|e| vec![e])?;
+    let (pins, pin_map) = parser::assign_pins(chip, pin_decls).map_err(|e| vec![e])?;
+    let symbols = Symbols::new(chip, pin_map);
+
+    let fields = HashMap::new();
+    let mut eqns = Vec::new();
+    let mut errors = Vec::new();
+    for statement in eqn_statements.into_iter() {
+        let end_line = cupl::last_line(&statement);
+        let statement = to_cupl_equation(chip, statement);
+        match cupl::parse_equation(&symbols, &fields, statement) {
+            Ok(eqn) => eqns.push(eqn),
+            Err(e) => errors.push(cupl::at_statement_end(e, end_line)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Content {
+        chip,
+        sig,
+        pins: parser::pin_names(pins),
+        eqns: cupl::convert_equations(eqns),
+        mode: None,
+        notes: Notes::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gal::Pin,
+        parser::{Expr, Suffix, LHS},
+    };
+
+    const DECODER: &str = "\
+MODULE decoder
+TITLE 'Address decoder'
+
+DECLARATIONS
+decoder DEVICE 'P16V8';
+CLK, A, B PIN 1, 2, 3;
+Q PIN 14 ISTYPE 'reg';
+!CS PIN 19 ISTYPE 'com';
+Y PIN 18;
+
+EQUATIONS
+Q := A & !Q.FB;  \"Feedback from the register
+CS = A # B;
+Y = A $ B;
+Y.OE = B;
+END
+";
+
+    fn pin(pin: usize, neg: bool) -> Expr {
+        Expr::Pin(Pin { pin, neg })
+    }
+
+    #[test]
+    fn design() {
+        let content = match parse_str(DECODER) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        assert_eq!(content.chip, Chip::GAL16V8);
+        assert_eq!(content.sig, b"decoder");
+        assert_eq!(&content.pins[..3], ["CLK", "A", "B"]);
+
+        let eqns = content
+            .eqns
+            .iter()
+            .map(|eqn| (eqn.lhs.clone(), eqn.rhs.clone()))
+            .collect::<Vec<_>>();
+        let output = |n, neg, suffix| LHS::Pin((Pin { pin: n, neg }, suffix));
+        assert_eq!(
+            eqns,
+            vec![
+                (
+                    output(14, false, Suffix::R),
+                    Expr::And(vec![pin(2, false), Expr::Not(Box::new(pin(14, false)))])
+                ),
+                // CS is declared active low.
+                (
+                    output(19, true, Suffix::None),
+                    Expr::Or(vec![pin(2, false), pin(3, false)])
+                ),
+                // The output enable makes Y tristate.
+                (
+                    output(18, false, Suffix::T),
+                    Expr::Xor(Box::new(pin(2, false)), Box::new(pin(3, false)))
+                ),
+                (output(18, false, Suffix::E), pin(3, false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let data = DECODER
+            .replace("CS = A # B;", "CS = A # Z;")
+            .replace("Y.OE", "Y.XX");
+        match parse_str(&data) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => assert!(matches!(
+                errors[..],
+                [
+                    Error {
+                        code: ErrorCode::UnknownPin { .. },
+                        line: 13,
+                        ..
+                    },
+                    Error {
+                        code: ErrorCode::BadSuffix { .. },
+                        line: 15,
+                        ..
+                    },
+                ]
+            )),
+        }
+
+        let data = DECODER.replace("decoder DEVICE 'P16V8';", "");
+        match parse_str(&data) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => assert!(matches!(
+                errors[..],
+                [Error {
+                    code: ErrorCode::NoDevice,
+                    ..
+                }]
+            )),
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let text = strip_comments("A \"comment\" B // more\n")
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect::<String>();
+        assert_eq!(text, "A  B \n");
+    }
+}
@@ -10,16 +10,20 @@ use errors::{OutputSuffix, SpecialProductTerm};
 use crate::{
     chips::Chip,
     errors::{self, Error, ErrorCode},
-    gal::{self, Pin, Term},
-    parser::{Content, Equation, Suffix, LHS},
+    gal::{self, Mode, Pin, Term},
+    minimise,
+    parser::{Content, Equation, Expr, Notes, Suffix, LHS},
 };
 
 // Blueprint stores everything we need to construct the GAL.
+#[derive(Clone)]
 pub struct Blueprint {
     // Data copied straight over from parser::Content.
     pub chip: Chip,
     pub sig: Vec<u8>,
     pub pins: Vec<String>,
+    pub mode: Option<Mode>,
+    pub notes: Notes,
     // The Equations, transformed.
     pub olmcs: Vec<OLMC>,
     // GAL22V10 only:
@@ -47,40 +51,106 @@ impl Blueprint {
             chip,
             sig: Vec::new(),
             pins: Vec::new(),
+            mode: None,
+            notes: Notes::default(),
             olmcs,
             ar: None,
             sp: None,
         }
     }
 
-    pub fn from(content: &Content) -> Result<Self, Error> {
+    pub fn from(content: &Content) -> Result<Self, Vec<Error>> {
         let mut blueprint = Blueprint::new(content.chip);
 
         blueprint.sig = content.sig.clone();
         blueprint.pins = content.pins.clone();
+        blueprint.mode = content.mode;
+        blueprint.notes = content.notes.clone();
 
         // Convert equations into data on the OLMCs.
+        let mut errors = Vec::new();
         for eqn in content.eqns.iter() {
-            errors::at_line(eqn.line_num, blueprint.add_equation(eqn))?;
+            errors::note(
+                &mut errors,
+                errors::at_line(eqn.line_num, blueprint.add_equation(eqn)),
+            );
         }
 
+        if !errors.is_empty() {
+            return Err(errors);
+        }
         Ok(blueprint)
     }
 
+    // Minimise all the terms, so that they use as few rows as
+    // possible. This means we no longer generate the same fuse map as
+    // galasm, so it's optional.
+    pub fn minimise(&mut self) {
+        let chip = self.chip;
+        for olmc in self.olmcs.iter_mut() {
+            if let Some((_, term)) = &mut olmc.output {
+                minimise_term(chip, term);
+            }
+            for term in [
+                &mut olmc.tri_con,
+                &mut olmc.clock,
+                &mut olmc.arst,
+                &mut olmc.aprst,
+            ]
+            .iter_mut()
+            .filter_map(|term| term.as_mut())
+            {
+                minimise_term(chip, term);
+            }
+        }
+        for term in [&mut self.ar, &mut self.sp]
+            .iter_mut()
+            .filter_map(|term| term.as_mut())
+        {
+            minimise_term(chip, term);
+        }
+
+        // Minimisation can remove every use of a pin, in which case its
+        // OLMC no longer provides feedback.
+        let mut feedback = vec![false; self.olmcs.len()];
+        let terms = self
+            .olmcs
+            .iter()
+            .flat_map(|olmc| {
+                olmc.output
+                    .iter()
+                    .map(|(_, term)| term)
+                    .chain(olmc.tri_con.iter())
+                    .chain(olmc.clock.iter())
+                    .chain(olmc.arst.iter())
+                    .chain(olmc.aprst.iter())
+            })
+            .chain(self.ar.iter())
+            .chain(self.sp.iter());
+        for input in terms.flat_map(|term| term.pins.iter().flatten()) {
+            if let Some(i) = chip.pin_to_olmc(input.pin) {
+                feedback[i] = true;
+            }
+        }
+        for (olmc, feedback) in self.olmcs.iter_mut().zip(feedback) {
+            olmc.feedback = feedback;
+        }
+    }
+
     // Add an equation to the blueprint, steering it to the appropriate OLMC.
     pub fn add_equation(&mut self, eqn: &Equation) -> Result<(), ErrorCode> {
         let olmcs = &mut self.olmcs;
 
+        let term = eqn_to_term(self.chip, eqn)?;
+
         // Mark all OLMCs that are inputs to other equations as providing feedback.
         // (Note they may actually be used as undriven inputs.)
-        for input in eqn.rhs.iter() {
+        for input in term.pins.iter().flatten() {
             if let Some(i) = self.chip.pin_to_olmc(input.pin) {
                 olmcs[i].feedback = true;
             }
         }
 
-        let term = eqn_to_term(self.chip, eqn)?;
-
         // AR/SP special cases:
         match eqn.lhs {
             LHS::Ar => {
@@ -138,8 +208,7 @@ impl Blueprint {
 // Term, which is close to the fuse map representation.
 fn eqn_to_term(chip: Chip, eqn: &Equation) -> Result<Term, ErrorCode> {
     // Special case for constant true or false.
-    if eqn.rhs.len() == 1 {
-        let pin = &eqn.rhs[0];
+    if let Expr::Pin(pin) = &eqn.rhs {
         if pin.pin == chip.num_pins() {
             // VCC
             if pin.neg {
@@ -161,25 +230,109 @@ fn eqn_to_term(chip: Chip, eqn: &Equation) -> Result<Term, ErrorCode> {
         }
     }
 
-    // Create a list of OR'd terms, each team being a group of AND'd terms.
-    let mut ors = Vec::new();
-    let mut ands = Vec::new();
+    Ok(Term {
+        line_num: eqn.line_num,
+        pins: expr_to_sop(&eqn.rhs, false)?,
+        xor_expanded: has_xor(&eqn.rhs),
+    })
+}
 
-    for (pin, is_or) in eqn.rhs.iter().zip(eqn.is_or.iter()) {
-        if *is_or {
-            ors.push(ands);
-            ands = Vec::new();
+// Convert an expression into a list of OR'd terms, each term being a
+// group of AND'd pins. Negations are pushed down to the pins using De
+// Morgan's laws, and ANDs of ORs are multiplied out. 'neg' is set if
+// the expression is to be negated.
+fn expr_to_sop(expr: &Expr, neg: bool) -> Result<Vec<Vec<Pin>>, ErrorCode> {
+    match expr {
+        Expr::Pin(pin) => Ok(vec![vec![Pin {
+            pin: pin.pin,
+            neg: pin.neg != neg,
+        }]]),
+        Expr::Not(inner) => expr_to_sop(inner, !neg),
+        // /(A * B) = /A + /B
+        Expr::And(exprs) if neg => sum_to_sop(exprs, neg),
+        Expr::And(exprs) => product_to_sop(exprs, neg),
+        // /(A + B) = /A * /B
+        Expr::Or(exprs) if neg => product_to_sop(exprs, neg),
+        Expr::Or(exprs) => sum_to_sop(exprs, neg),
+        // A $ B = A * /B + /A * B, and /(A $ B) = A * B + /A * /B
+        Expr::Xor(lhs, rhs) => {
+            let mut products = and_sops(&expr_to_sop(lhs, false)?, &expr_to_sop(rhs, !neg)?)?;
+            products.extend(and_sops(&expr_to_sop(lhs, true)?, &expr_to_sop(rhs, neg)?)?);
+            check_expanded(products)
         }
-        ands.push(*pin);
     }
-    ors.push(ands);
+}
 
-    Ok(Term {
-        line_num: eqn.line_num,
-        pins: ors,
+fn sum_to_sop(exprs: &[Expr], neg: bool) -> Result<Vec<Vec<Pin>>, ErrorCode> {
+    let mut products = Vec::new();
+    for expr in exprs.iter() {
+        products.extend(expr_to_sop(expr, neg)?);
+        products = check_expanded(products)?;
+    }
+    Ok(products)
+}
+
+fn product_to_sop(exprs: &[Expr], neg: bool) -> Result<Vec<Vec<Pin>>, ErrorCode> {
+    // Start from true (a single empty product), and distribute each
+    // factor over the products so far.
+    exprs.iter().try_fold(vec![Vec::new()], |products, expr| {
+        and_sops(&products, &expr_to_sop(expr, neg)?)
     })
 }
 
+// AND together two sums of products, multiplying them out.
+fn and_sops(lhs: &[Vec<Pin>], rhs: &[Vec<Pin>]) -> Result<Vec<Vec<Pin>>, ErrorCode> {
+    // Check the size before building the products, not after.
+    if lhs.len().saturating_mul(rhs.len()) > minimise::MAX_PRODUCTS {
+        return Err(too_many_expanded());
+    }
+    Ok(lhs
+        .iter()
+        .flat_map(|l| {
+            rhs.iter().map(move |r| {
+                let mut product = l.clone();
+                product.extend_from_slice(r);
+                product
+            })
+        })
+        .collect())
+}
+
+// Multiplying out can blow up exponentially (e.g. a negated sum of
+// products), so give up past a limit rather than run out of memory.
+fn check_expanded(products: Vec<Vec<Pin>>) -> Result<Vec<Vec<Pin>>, ErrorCode> {
+    if products.len() > minimise::MAX_PRODUCTS {
+        return Err(too_many_expanded());
+    }
+    Ok(products)
+}
+
+fn too_many_expanded() -> ErrorCode {
+    ErrorCode::TooManyExpandedProducts {
+        max: minimise::MAX_PRODUCTS,
+    }
+}
+
+fn minimise_term(chip: Chip, term: &mut Term) {
+    // Leave terms that use VCC or GND alone, so that they're reported
+    // as errors rather than optimised away.
+    let is_power = |pin: &Pin| pin.pin == chip.num_pins() || pin.pin == chip.num_pins() / 2;
+    if term.pins.iter().flatten().any(is_power) {
+        return;
+    }
+
+    term.pins = minimise::minimise(&term.pins);
+}
+
+fn has_xor(expr: &Expr) -> bool {
+    match expr {
+        Expr::Pin(_) => false,
+        Expr::Not(inner) => has_xor(inner),
+        Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().any(has_xor),
+        Expr::Xor(_, _) => true,
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////
 // The OLMC structure, representing the logic for an output pin.
 //
@@ -228,7 +381,7 @@ impl OLMC {
             });
         }
 
-        if self.tri_con != None {
+        if self.tri_con.is_some() {
             return Err(ErrorCode::RepeatedControl {
                 suffix: OutputSuffix::E,
             });
@@ -289,3 +442,123 @@ impl OLMC {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(pin: usize, neg: bool) -> Pin {
+        Pin { pin, neg }
+    }
+
+    fn p(n: usize) -> Expr {
+        Expr::Pin(pin(n, false))
+    }
+
+    #[test]
+    fn minimised_feedback() {
+        // Q's only use is absorbed by A.
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\nR = A + A * Q\n";
+        let content = match crate::parser::parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        let mut blueprint = match Blueprint::from(&content) {
+            Ok(blueprint) => blueprint,
+            Err(_) => panic!("blueprint failed"),
+        };
+        let q = Chip::GAL16V8.pin_to_olmc(18).unwrap();
+        assert!(blueprint.olmcs[q].feedback);
+        blueprint.minimise();
+        assert!(!blueprint.olmcs[q].feedback);
+    }
+
+    #[test]
+    fn sop_limit() {
+        // /(A * B + C * D + ...) multiplies out to 2^n products.
+        let negated_sum = |n: usize| {
+            let products = (0..n)
+                .map(|i| Expr::And(vec![p(2 * i + 1), p(2 * i + 2)]))
+                .collect();
+            Expr::Not(Box::new(Expr::Or(products)))
+        };
+        assert_eq!(expr_to_sop(&negated_sum(9), false).unwrap().len(), 512);
+        for n in [10, 40] {
+            assert!(matches!(
+                expr_to_sop(&negated_sum(n), false),
+                Err(ErrorCode::TooManyExpandedProducts { max: 512 })
+            ));
+        }
+    }
+
+    #[test]
+    fn sop_flat() {
+        // A * B + C
+        let expr = Expr::Or(vec![Expr::And(vec![p(1), p(2)]), p(3)]);
+        assert_eq!(
+            expr_to_sop(&expr, false).unwrap(),
+            vec![vec![pin(1, false), pin(2, false)], vec![pin(3, false)]]
+        );
+    }
+
+    #[test]
+    fn sop_distribute() {
+        // (A + B) * (C + D)
+        let expr = Expr::And(vec![Expr::Or(vec![p(1), p(2)]), Expr::Or(vec![p(3), p(4)])]);
+        assert_eq!(
+            expr_to_sop(&expr, false).unwrap(),
+            vec![
+                vec![pin(1, false), pin(3, false)],
+                vec![pin(1, false), pin(4, false)],
+                vec![pin(2, false), pin(3, false)],
+                vec![pin(2, false), pin(4, false)],
+            ]
+        );
+    }
+
+    #[test]
+    fn sop_de_morgan() {
+        // /(A + B) * (C + /D)
+        let expr = Expr::And(vec![
+            Expr::Not(Box::new(Expr::Or(vec![p(1), p(2)]))),
+            Expr::Or(vec![p(3), Expr::Pin(pin(4, true))]),
+        ]);
+        assert_eq!(
+            expr_to_sop(&expr, false).unwrap(),
+            vec![
+                vec![pin(1, true), pin(2, true), pin(3, false)],
+                vec![pin(1, true), pin(2, true), pin(4, true)],
+            ]
+        );
+
+        // /(A * /B)
+        let expr = Expr::Not(Box::new(Expr::And(vec![p(1), Expr::Pin(pin(2, true))])));
+        assert_eq!(
+            expr_to_sop(&expr, false).unwrap(),
+            vec![vec![pin(1, true)], vec![pin(2, false)]]
+        );
+    }
+
+    #[test]
+    fn sop_xor() {
+        // A $ B
+        let expr = Expr::Xor(Box::new(p(1)), Box::new(p(2)));
+        assert_eq!(
+            expr_to_sop(&expr, false).unwrap(),
+            vec![
+                vec![pin(1, false), pin(2, true)],
+                vec![pin(1, true), pin(2, false)],
+            ]
+        );
+
+        // A !$ B
+        let expr = Expr::Not(Box::new(expr));
+        assert_eq!(
+            expr_to_sop(&expr, false).unwrap(),
+            vec![
+                vec![pin(1, false), pin(2, false)],
+                vec![pin(1, true), pin(2, true)],
+            ]
+        );
+    }
+}
@@ -21,12 +21,18 @@ pub struct Bounds {
 // parameters. Some things vary across the differing GAL{16,20}V8 modes,
 // and those things can't be queried on 'Chip'. Look them up on 'GAL'
 // instead.
+//
+// The Atmel (now Microchip) ATF parts are fuse-compatible with the
+// Lattice GALs, so match on 'family' for the behaviour they share.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Chip {
     GAL16V8,
     GAL20V8,
     GAL22V10,
     GAL20RA10,
+    ATF16V8B,
+    ATF20V8B,
+    ATF22V10C,
 }
 
 // 'ChipData' stores these per-chip-type parameters, so that the
@@ -98,11 +104,32 @@ const GAL20RA10_DATA: ChipData = ChipData {
     olmc_map: &OLMC_ROWS_20RA10,
 };
 
+const ATF16V8B_DATA: ChipData = ChipData {
+    name: "ATF16V8B",
+    ..GAL16V8_DATA
+};
+
+const ATF20V8B_DATA: ChipData = ChipData {
+    name: "ATF20V8B",
+    ..GAL20V8_DATA
+};
+
+// The ATF22V10C has an extra fuse after the signature, to enable the
+// power-down pin.
+const ATF22V10C_DATA: ChipData = ChipData {
+    name: "ATF22V10C",
+    total_size: 5893,
+    ..GAL22V10_DATA
+};
+
 // These constants are used to get the fuse row bounds associated with
 // the OLMCs.
 
 // Number of rows for each OLMC in the 22V10's fuse table (only 22V10
 // is non-uniform).
+// The most pins on any supported chip.
+pub const MAX_PINS: usize = 24;
+
 const OLMC_SIZE_22V10: [i32; 10] = [9, 11, 13, 15, 17, 17, 15, 13, 11, 9];
 // And for all the other chips, they have 8 rows per OLMC:
 const OLMC_SIZE_DEFAULT: i32 = 8;
@@ -119,18 +146,84 @@ impl Chip {
             "GAL20V8" => Ok(Chip::GAL20V8),
             "GAL22V10" => Ok(Chip::GAL22V10),
             "GAL20RA10" => Ok(Chip::GAL20RA10),
+            "ATF16V8B" => Ok(Chip::ATF16V8B),
+            "ATF20V8B" => Ok(Chip::ATF20V8B),
+            "ATF22V10C" => Ok(Chip::ATF22V10C),
             _ => Err(ErrorCode::BadGALType {
                 gal: name.to_string(),
             }),
         }
     }
 
+    // Find the GAL that can stand in for a part, given its name as
+    // written in PALASM or ABEL sources (e.g. 'PAL16L8', 'P16R4',
+    // 'PALCE22V10'). Speed grades and other suffixes are ignored.
+    pub fn from_pal_name(name: &str) -> Result<Chip, ErrorCode> {
+        let upper = name.to_ascii_uppercase();
+
+        // ATF parts are used as themselves.
+        let atf_parts = [
+            ("ATF16V8", Chip::ATF16V8B),
+            ("ATF20V8", Chip::ATF20V8B),
+            ("ATF22V10", Chip::ATF22V10C),
+        ];
+        if let Some((_, chip)) = atf_parts.iter().find(|(p, _)| upper.starts_with(p)) {
+            return Ok(*chip);
+        }
+
+        let part = ["PALCE", "PAL", "GAL", "P", "G"]
+            .iter()
+            .find_map(|prefix| upper.strip_prefix(prefix))
+            .unwrap_or(&upper);
+
+        let equivalents: &[(&[&str], Chip)] = &[
+            (&["22V10"], Chip::GAL22V10),
+            (&["20RA10"], Chip::GAL20RA10),
+            (
+                &[
+                    "16V8", "10H8", "10L8", "12H6", "12L6", "14H4", "14L4", "16H2", "16L2", "16C1",
+                    "16H8", "16L8", "16P8", "16R4", "16R6", "16R8", "16RP4", "16RP6", "16RP8",
+                ],
+                Chip::GAL16V8,
+            ),
+            (
+                &[
+                    "20V8", "12L10", "14L8", "16L6", "18L4", "20L2", "20C1", "20H8", "20L8",
+                    "20P8", "20R4", "20R6", "20R8", "20RP4", "20RP6", "20RP8",
+                ],
+                Chip::GAL20V8,
+            ),
+        ];
+        for (parts, chip) in equivalents.iter() {
+            if parts.iter().any(|p| part.starts_with(p)) {
+                return Ok(*chip);
+            }
+        }
+
+        Err(ErrorCode::BadGALType {
+            gal: name.to_string(),
+        })
+    }
+
     fn get_chip_data(&self) -> &ChipData {
         match self {
             Chip::GAL16V8 => &GAL16V8_DATA,
             Chip::GAL20V8 => &GAL20V8_DATA,
             Chip::GAL22V10 => &GAL22V10_DATA,
             Chip::GAL20RA10 => &GAL20RA10_DATA,
+            Chip::ATF16V8B => &ATF16V8B_DATA,
+            Chip::ATF20V8B => &ATF20V8B_DATA,
+            Chip::ATF22V10C => &ATF22V10C_DATA,
+        }
+    }
+
+    // The Lattice GAL that the part is fuse-compatible with.
+    pub fn family(&self) -> Chip {
+        match self {
+            Chip::ATF16V8B => Chip::GAL16V8,
+            Chip::ATF20V8B => Chip::GAL20V8,
+            Chip::ATF22V10C => Chip::GAL22V10,
+            chip => *chip,
         }
     }
 
@@ -164,6 +257,11 @@ impl Chip {
         }
     }
 
+    // Pin number of the given OLMC.
+    pub fn olmc_to_pin(&self, olmc_num: usize) -> usize {
+        self.get_chip_data().min_olmc_pin + olmc_num
+    }
+
     // Pin number of last OLMC'd output.
     pub fn last_olmc(&self) -> usize {
         self.get_chip_data().max_olmc_pin
@@ -177,7 +275,7 @@ impl Chip {
 
     // Not everything is easiest driven off a table...
     pub fn num_rows_for_olmc(&self, olmc_num: usize) -> usize {
-        if *self == Chip::GAL22V10 {
+        if self.family() == Chip::GAL22V10 {
             // Only 22V10 has non-uniform-sized OLMCs.
             OLMC_SIZE_22V10[olmc_num] as usize
         } else {
@@ -0,0 +1,974 @@
+//
+// cupl.rs: CUPL input parser
+//
+// Read the subset of WinCUPL's .pld format that covers simple GAL
+// designs, and convert it to the same 'Content' structure as the
+// galasm-style parser produces. Supported are the header statements
+// ('Name', 'Device', etc.), 'PIN' and 'FIELD' declarations, and
+// equations using '!', '&', '#' and '$', with the '.d', '.oe', '.ar',
+// '.sp' and '.ck' extensions.
+//
+// Statements are split on ';', then lexed into CUPL tokens. Equation
+// right-hand sides are translated into the galasm parser's tokens so
+// that we can share its expression parser.
+//
+
+use std::{collections::HashMap, iter::Peekable};
+
+use crate::{
+    chips::Chip,
+    errors::{at_line, Error, ErrorCode, LineNum, Pos},
+    gal::Pin,
+    parser::{
+        self, Content, Equation, Expr, NamedPin, Notes, Suffix, Symbols, Token, EOF_LINE, LHS,
+    },
+};
+
+////////////////////////////////////////////////////////////////////////
+// Lexing
+//
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Lexeme {
+    Name(String),
+    // Numbers are kept as written, as their base depends on context.
+    Number(String),
+    Range,
+    Punct(char),
+}
+
+pub(crate) type Statement = Vec<(LineNum, Lexeme)>;
+
+// Header statements, which we mostly ignore.
+const HEADERS: &[&str] = &[
+    "NAME", "PARTNO", "DATE", "REVISION", "REV", "DESIGNER", "COMPANY", "ASSEMBLY", "ASSY",
+    "LOCATION", "DEVICE", "FORMAT",
+];
+
+// Strip '/* ... */' comments, returning the remaining characters with
+// their line numbers.
+fn strip_comments(data: &str) -> Vec<(LineNum, char)> {
+    let mut res = Vec::new();
+    let mut in_comment = false;
+    for (line_num, line) in (1..).zip(data.lines()) {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_comment {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    in_comment = false;
+                }
+            } else if c == '/' && chars.peek() == Some(&'*') {
+                chars.next();
+                in_comment = true;
+            } else {
+                res.push((line_num, c));
+            }
+        }
+        res.push((line_num, '\n'));
+    }
+    res
+}
+
+// Split the input into ';'-terminated statements, and lex each one.
+// A final statement with no ';' is accepted.
+fn statements(data: &str) -> Result<Vec<Statement>, Error> {
+    let chars = strip_comments(data);
+    chars
+        .split(|(_, c)| *c == ';')
+        .map(|chars| match lex_header(chars) {
+            Some(statement) => Ok(statement),
+            None => lex(chars),
+        })
+        .filter(|res| !matches!(res, Ok(statement) if statement.is_empty()))
+        .collect()
+}
+
+// Header values are free text (dates, names, etc.), so they're kept
+// as a single name rather than lexed.
+fn lex_header(chars: &[(LineNum, char)]) -> Option<Statement> {
+    let mut chars = chars
+        .iter()
+        .skip_while(|(_, c)| c.is_whitespace())
+        .peekable();
+    let line_num = chars.peek()?.0;
+    let keyword = chars
+        .by_ref()
+        .take_while(|(_, c)| !c.is_whitespace())
+        .map(|(_, c)| *c)
+        .collect::<String>();
+    if !HEADERS.contains(&keyword.to_ascii_uppercase().as_str()) {
+        return None;
+    }
+
+    let value = chars.map(|(_, c)| *c).collect::<String>();
+    let mut res = vec![(line_num, Lexeme::Name(keyword))];
+    if !value.trim().is_empty() {
+        res.push((line_num, Lexeme::Name(value.trim().to_string())));
+    }
+    Some(res)
+}
+
+pub(crate) fn lex(chars: &[(LineNum, char)]) -> Result<Statement, Error> {
+    let mut res = Vec::new();
+    let mut chars = chars.iter().cloned().peekable();
+    while let Some((line_num, c)) = chars.next() {
+        let lexeme = match c {
+            c if c.is_whitespace() => continue,
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                name.push_str(&take_while(&mut chars, |c| {
+                    c.is_ascii_alphanumeric() || c == '_'
+                }));
+                Lexeme::Name(name)
+            }
+            c if c.is_ascii_digit() => {
+                let mut num = c.to_string();
+                num.push_str(&take_while(&mut chars, |c| c.is_ascii_alphanumeric()));
+                Lexeme::Number(num)
+            }
+            // Numbers with an explicit base, e.g. 'b'0101.
+            '\'' => {
+                let mut num = c.to_string();
+                num.push_str(&take_while(&mut chars, |c| c != '\''));
+                if chars.next().is_none() {
+                    return parser::err(line_num, ErrorCode::BadNumber { num });
+                }
+                num.push('\'');
+                num.push_str(&take_while(&mut chars, |c| c.is_ascii_alphanumeric()));
+                Lexeme::Number(num)
+            }
+            '.' if matches!(chars.peek(), Some((_, '.'))) => {
+                chars.next();
+                Lexeme::Range
+            }
+            '!' | '&' | '#' | '$' | '(' | ')' | '=' | ':' | '[' | ']' | ',' | '.' => {
+                Lexeme::Punct(c)
+            }
+            c => return parser::err(line_num, ErrorCode::BadChar { c }),
+        };
+        res.push((line_num, lexeme));
+    }
+    Ok(res)
+}
+
+fn take_while<I, F>(chars: &mut Peekable<I>, f: F) -> String
+where
+    I: Iterator<Item = (LineNum, char)>,
+    F: Fn(char) -> bool,
+{
+    let mut res = String::new();
+    while let Some((_, c)) = chars.peek().cloned().filter(|(_, c)| f(*c)) {
+        chars.next();
+        res.push(c);
+    }
+    res
+}
+
+// Does the data look like CUPL? CUPL files start with header
+// statements, while galasm files start with the chip name.
+pub fn is_cupl(data: &str) -> bool {
+    let chars = strip_comments(data);
+    let first_word = chars
+        .iter()
+        .map(|(_, c)| *c)
+        .skip_while(|c| c.is_whitespace())
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>();
+    HEADERS.contains(&first_word.to_ascii_uppercase().as_str())
+}
+
+////////////////////////////////////////////////////////////////////////
+// Parsing
+//
+
+// Statement keywords are case-insensitive.
+fn keyword(statement: &Statement) -> Option<String> {
+    match statement.first() {
+        Some((_, Lexeme::Name(name))) => Some(name.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+// Line number to report for errors at the end of a statement.
+pub(crate) fn last_line(statement: &Statement) -> LineNum {
+    statement.last().map(|(line_num, _)| *line_num).unwrap_or(1)
+}
+
+fn expect<I>(tokens: &mut Peekable<I>, end_line: LineNum, c: char) -> Result<(), Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+{
+    let expected = match c {
+        '=' => "'='",
+        ']' => "']'",
+        _ => "punctuation",
+    };
+    match tokens.next() {
+        Some((_, Lexeme::Punct(p))) if p == c => Ok(()),
+        Some((line_num, _)) => parser::err(line_num, ErrorCode::BadToken { expected }),
+        None => parser::err(end_line, ErrorCode::BadToken { expected }),
+    }
+}
+
+fn expect_end<I>(tokens: &mut I) -> Result<(), Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+{
+    match tokens.next() {
+        Some((line_num, _)) => parser::err(line_num, ErrorCode::BadToken { expected: "';'" }),
+        None => Ok(()),
+    }
+}
+
+// Map a CUPL device name (e.g. 'g16v8a', or a full ATF part name) to
+// the chip.
+fn device_to_chip(name: &str) -> Result<Chip, ErrorCode> {
+    let lower = name.to_ascii_lowercase();
+    Ok(if lower.starts_with("g16v8") {
+        Chip::GAL16V8
+    } else if lower.starts_with("g20v8") {
+        Chip::GAL20V8
+    } else if lower.starts_with("g22v10") {
+        Chip::GAL22V10
+    } else if lower.starts_with("g20ra10") {
+        Chip::GAL20RA10
+    } else if lower.starts_with("atf16v8") {
+        Chip::ATF16V8B
+    } else if lower.starts_with("atf20v8") {
+        Chip::ATF20V8B
+    } else if lower.starts_with("atf22v10") {
+        Chip::ATF22V10C
+    } else {
+        return Err(ErrorCode::BadGALType {
+            gal: name.to_string(),
+        });
+    })
+}
+
+// Parse a decimal pin number, or a list of them (e.g. '[2..5, 7]').
+fn parse_pin_numbers<I>(tokens: &mut Peekable<I>, end_line: LineNum) -> Result<Vec<usize>, Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+{
+    fn decimal(line_num: LineNum, num: &str) -> Result<usize, Error> {
+        num.parse().or_else(|_| {
+            parser::err(
+                line_num,
+                ErrorCode::BadNumber {
+                    num: num.to_string(),
+                },
+            )
+        })
+    }
+
+    match tokens.next() {
+        Some((line_num, Lexeme::Number(num))) => Ok(vec![decimal(line_num, &num)?]),
+        Some((_, Lexeme::Punct('['))) => {
+            let mut res = Vec::new();
+            loop {
+                let (line_num, first) = match tokens.next() {
+                    Some((line_num, Lexeme::Number(num))) => (line_num, decimal(line_num, &num)?),
+                    Some((line_num, _)) => {
+                        return parser::err(line_num, ErrorCode::BadToken { expected: "number" })
+                    }
+                    None => return parser::err(end_line, ErrorCode::BadToken { expected: "]" }),
+                };
+                if let Some((_, Lexeme::Range)) = tokens.peek() {
+                    tokens.next();
+                    let last = match tokens.next() {
+                        Some((line_num, Lexeme::Number(num))) => decimal(line_num, &num)?,
+                        _ => return parser::err(line_num, ErrorCode::BadBusRange),
+                    };
+                    let (first, last) = at_line(line_num, parser::check_bus_range((first, last)))?;
+                    if first <= last {
+                        res.extend(first..=last);
+                    } else {
+                        res.extend((last..=first).rev());
+                    }
+                } else {
+                    res.push(first);
+                }
+                if !next_in_list(tokens, end_line)? {
+                    return Ok(res);
+                }
+            }
+        }
+        Some((line_num, _)) => parser::err(line_num, ErrorCode::BadToken { expected: "number" }),
+        None => parser::err(end_line, ErrorCode::BadToken { expected: "number" }),
+    }
+}
+
+// Parse a pin name, or a list of them (e.g. '[A3..0, !B]'), returning
+// the names with their negations.
+fn parse_names<I>(tokens: &mut Peekable<I>, end_line: LineNum) -> Result<Vec<(String, bool)>, Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+{
+    fn name<I>(tokens: &mut Peekable<I>, end_line: LineNum) -> Result<(String, bool), Error>
+    where
+        I: Iterator<Item = (LineNum, Lexeme)>,
+    {
+        let neg = matches!(tokens.peek(), Some((_, Lexeme::Punct('!'))));
+        if neg {
+            tokens.next();
+        }
+        match tokens.next() {
+            Some((_, Lexeme::Name(name))) => Ok((name, neg)),
+            Some((line_num, _)) => parser::err(line_num, ErrorCode::BadToken { expected: "pin" }),
+            None => parser::err(end_line, ErrorCode::BadToken { expected: "pin" }),
+        }
+    }
+
+    if !matches!(tokens.peek(), Some((_, Lexeme::Punct('[')))) {
+        return Ok(vec![name(tokens, end_line)?]);
+    }
+    tokens.next();
+
+    let mut res = Vec::new();
+    loop {
+        let line_num = tokens
+            .peek()
+            .map(|(line_num, _)| *line_num)
+            .unwrap_or(end_line);
+        let (first, neg) = name(tokens, end_line)?;
+        if let Some((_, Lexeme::Range)) = tokens.peek() {
+            // A range is written 'A0..3' or 'A0..A3'.
+            tokens.next();
+            let last = match tokens.next() {
+                Some((_, Lexeme::Number(num))) => num,
+                Some((_, Lexeme::Name(name))) => name,
+                _ => return parser::err(line_num, ErrorCode::BadBusRange),
+            };
+            let (prefix, first) =
+                at_line(line_num, split_index(&first).ok_or(ErrorCode::BadBusRange))?;
+            let last = match split_index(&last) {
+                Some((last_prefix, last)) if last_prefix == prefix || last_prefix.is_empty() => {
+                    last
+                }
+                _ => return parser::err(line_num, ErrorCode::BadBusRange),
+            };
+            let range = at_line(line_num, parser::check_bus_range((first, last)))?;
+            for (name, _) in parser::bus_bits(prefix, range) {
+                res.push((name, neg));
+            }
+        } else {
+            res.push((first, neg));
+        }
+        if !next_in_list(tokens, end_line)? {
+            return Ok(res);
+        }
+    }
+}
+
+// Split a name like 'A12' into 'A' and 12.
+fn split_index(name: &str) -> Option<(&str, usize)> {
+    let idx = name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (prefix, digits) = name.split_at(idx);
+    digits.parse().ok().map(|n| (prefix, n))
+}
+
+// After a list item, move past the ',' and return true, or past the
+// ']' and return false.
+fn next_in_list<I>(tokens: &mut Peekable<I>, end_line: LineNum) -> Result<bool, Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+{
+    match tokens.next() {
+        Some((_, Lexeme::Punct(','))) => Ok(true),
+        Some((_, Lexeme::Punct(']'))) => Ok(false),
+        Some((line_num, _)) => parser::err(
+            line_num,
+            ErrorCode::BadToken {
+                expected: "',' or ']'",
+            },
+        ),
+        None => parser::err(end_line, ErrorCode::BadToken { expected: "]" }),
+    }
+}
+
+// Parse a number, which is hex unless it has an explicit base. Binary,
+// octal and hex numbers may contain 'X' for don't care bits. Returns
+// the value, and a mask of the bits we care about.
+fn parse_number(num: &str) -> Result<(u64, u64), ErrorCode> {
+    let bad_number = || ErrorCode::BadNumber {
+        num: num.to_string(),
+    };
+
+    let lower = num.to_ascii_lowercase();
+    let (bits_per_digit, digits) = if let Some(rest) = lower.strip_prefix('\'') {
+        match rest.split_once('\'') {
+            Some(("b", digits)) => (1, digits.to_string()),
+            Some(("o", digits)) => (3, digits.to_string()),
+            Some(("h", digits)) => (4, digits.to_string()),
+            Some(("d", digits)) => {
+                return digits
+                    .parse()
+                    .map(|n| (n, u64::MAX))
+                    .map_err(|_| bad_number())
+            }
+            _ => return Err(bad_number()),
+        }
+    } else {
+        (4, lower)
+    };
+
+    if digits.is_empty() || digits.len() * bits_per_digit > 64 {
+        return Err(bad_number());
+    }
+
+    let digit_mask = (1 << bits_per_digit) - 1;
+    let mut value = 0;
+    let mut care = 0;
+    for c in digits.chars() {
+        value <<= bits_per_digit;
+        care <<= bits_per_digit;
+        if c != 'x' {
+            let digit = c.to_digit(1 << bits_per_digit).ok_or_else(bad_number)? as u64;
+            value |= digit;
+            care |= digit_mask;
+        }
+    }
+    Ok((value, care))
+}
+
+// Translate the CUPL right-hand side of an equation into the galasm
+// parser's tokens. Constants become VCC or GND, and field comparisons
+// are turned into products.
+fn translate_expr<I>(
+    symbols: &Symbols,
+    fields: &HashMap<String, Vec<String>>,
+    tokens: &mut Peekable<I>,
+) -> Result<Vec<(Pos, Token)>, Error>
+where
+    I: Iterator<Item = (LineNum, Lexeme)>,
+{
+    let mut res = Vec::new();
+    while let Some((line_num, lexeme)) = tokens.next() {
+        let token = match lexeme {
+            Lexeme::Name(name) => match fields.get(&name) {
+                Some(field) => {
+                    expect(tokens, line_num, ':')?;
+                    let (value, care) = match tokens.next() {
+                        // Hex values like 'A' look like names.
+                        Some((line_num, Lexeme::Number(num)))
+                        | Some((line_num, Lexeme::Name(num))) => {
+                            at_line(line_num, parse_number(&num))?
+                        }
+                        Some((line_num, _)) => {
+                            return parser::err(
+                                line_num,
+                                ErrorCode::BadToken { expected: "number" },
+                            )
+                        }
+                        None => {
+                            return parser::err(
+                                line_num,
+                                ErrorCode::BadToken { expected: "number" },
+                            )
+                        }
+                    };
+                    Token::Expr(field_compare(symbols, line_num, field, value, care)?)
+                }
+                None => Token::Item((
+                    NamedPin {
+                        name,
+                        neg: false,
+                        bus: None,
+                    },
+                    Suffix::None,
+                )),
+            },
+            Lexeme::Number(num) => {
+                let name = match at_line(line_num, parse_number(&num))? {
+                    (0, _) => "GND",
+                    (1, _) => "VCC",
+                    _ => return parser::err(line_num, ErrorCode::BadNumber { num }),
+                };
+                Token::Item((
+                    NamedPin {
+                        name: name.to_string(),
+                        neg: false,
+                        bus: None,
+                    },
+                    Suffix::None,
+                ))
+            }
+            Lexeme::Punct('!') if matches!(tokens.peek(), Some((_, Lexeme::Punct('$')))) => {
+                tokens.next();
+                Token::Xnor
+            }
+            Lexeme::Punct('!') => Token::Not,
+            Lexeme::Punct('&') => Token::And,
+            Lexeme::Punct('#') => Token::Or,
+            Lexeme::Punct('$') => Token::Xor,
+            Lexeme::Punct('(') => Token::LParen,
+            Lexeme::Punct(')') => Token::RParen,
+            Lexeme::Punct(c) => return parser::err(line_num, ErrorCode::BadChar { c }),
+            Lexeme::Range => return parser::err(line_num, ErrorCode::BadToken { expected: "pin" }),
+        };
+        res.push((line_num.into(), token));
+    }
+    Ok(res)
+}
+
+// Compare a field against a value, ignoring the don't care bits. The
+// first pin of the field is the most significant.
+fn field_compare(
+    symbols: &Symbols,
+    line_num: LineNum,
+    field: &[String],
+    value: u64,
+    care: u64,
+) -> Result<Expr, Error> {
+    let width = field.len();
+    if width < 64 && value >> width != 0 {
+        return parser::err(line_num, ErrorCode::BusValueTooWide { value, width });
+    }
+
+    let mut factors = Vec::new();
+    for (name, bit) in field.iter().zip((0..width).rev()) {
+        if bit < 64 && (care >> bit) & 1 == 1 {
+            let pin = lookup(symbols, line_num, name)?;
+            factors.push(Expr::Pin(Pin {
+                pin: pin.pin,
+                neg: pin.neg == ((value >> bit) & 1 == 1),
+            }));
+        }
+    }
+    Ok(Expr::And(factors))
+}
+
+fn lookup(symbols: &Symbols, line_num: LineNum, name: &str) -> Result<Pin, Error> {
+    match symbols.pins.get(name) {
+        Some(pin) => Ok(*pin),
+        None => parser::err(
+            line_num,
+            ErrorCode::UnknownPin {
+                name: name.to_string(),
+            },
+        ),
+    }
+}
+
+// Parse an equation, of the form '[!]name[.ext] = expr'.
+pub(crate) fn parse_equation(
+    symbols: &Symbols,
+    fields: &HashMap<String, Vec<String>>,
+    statement: Statement,
+) -> Result<Equation, Error> {
+    let end_line = last_line(&statement);
+    let mut tokens = statement.into_iter().peekable();
+
+    let neg = matches!(tokens.peek(), Some((_, Lexeme::Punct('!'))));
+    if neg {
+        tokens.next();
+    }
+    let (line_num, name) = match tokens.next() {
+        Some((line_num, Lexeme::Name(name))) => (line_num, name),
+        Some((line_num, _)) => {
+            return parser::err(line_num, ErrorCode::BadToken { expected: "pin" })
+        }
+        None => return parser::err(end_line, ErrorCode::BadToken { expected: "pin" }),
+    };
+
+    let ext = if let Some((_, Lexeme::Punct('.'))) = tokens.peek() {
+        tokens.next();
+        match tokens.next() {
+            Some((_, Lexeme::Name(ext))) => Some(ext.to_ascii_lowercase()),
+            Some((line_num, _)) => {
+                return parser::err(
+                    line_num,
+                    ErrorCode::BadToken {
+                        expected: "extension",
+                    },
+                )
+            }
+            None => {
+                return parser::err(
+                    end_line,
+                    ErrorCode::BadToken {
+                        expected: "extension",
+                    },
+                )
+            }
+        }
+    } else {
+        None
+    };
+
+    let chip = symbols.chip;
+    let suffix = match ext.as_deref() {
+        None => Suffix::None,
+        Some("d") => Suffix::R,
+        Some("oe") => Suffix::E,
+        Some("ck") => Suffix::CLK,
+        Some("ar") if chip.family() == Chip::GAL20RA10 => Suffix::ARST,
+        Some("sp") if chip.family() == Chip::GAL20RA10 => Suffix::APRST,
+        Some("ar") | Some("sp") if chip.family() == Chip::GAL22V10 => Suffix::None,
+        Some(ext) => {
+            return parser::err(
+                line_num,
+                ErrorCode::BadSuffix {
+                    suffix: ext.to_string(),
+                },
+            )
+        }
+    };
+
+    // On the GAL22V10, AR and SP are shared by all outputs, and CUPL
+    // writes them against each output.
+    let lhs = match ext.as_deref() {
+        Some("ar") if chip.family() == Chip::GAL22V10 => LHS::Ar,
+        Some("sp") if chip.family() == Chip::GAL22V10 => LHS::Sp,
+        _ => {
+            let token = Token::Item((
+                NamedPin {
+                    name,
+                    neg,
+                    bus: None,
+                },
+                suffix,
+            ));
+            parser::parse_lhs(symbols, &mut std::iter::once((line_num.into(), token)))?
+        }
+    };
+
+    expect(&mut tokens, end_line, '=')?;
+
+    let rhs_tokens = translate_expr(symbols, fields, &mut tokens)?;
+    let mut rhs_tokens = rhs_tokens.into_iter().peekable();
+    let rhs = parser::parse_expr(symbols, &mut rhs_tokens)?;
+    if let Some((line_num, _)) = rhs_tokens.next() {
+        return parser::err(
+            line_num,
+            ErrorCode::BadToken {
+                expected: "&, #, or $",
+            },
+        );
+    }
+
+    Ok(Equation { line_num, lhs, rhs })
+}
+
+// Turn the conventions shared by CUPL, PALASM and ABEL into galette
+// ones: an output with an output enable is a tristate output, and
+// repeated identical AR and SP equations are dropped.
+pub(crate) fn convert_equations(eqns: Vec<Equation>) -> Vec<Equation> {
+    let enabled = eqns
+        .iter()
+        .filter_map(|eqn| match eqn.lhs {
+            LHS::Pin((pin, Suffix::E)) => Some(pin.pin),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut res: Vec<Equation> = Vec::new();
+    for mut eqn in eqns.into_iter() {
+        let is_special = eqn.lhs == LHS::Ar || eqn.lhs == LHS::Sp;
+        if is_special && res.iter().any(|e| e.lhs == eqn.lhs && e.rhs == eqn.rhs) {
+            continue;
+        }
+        if let LHS::Pin((pin, Suffix::None)) = eqn.lhs {
+            if enabled.contains(&pin.pin) {
+                eqn.lhs = LHS::Pin((pin, Suffix::T));
+            }
+        }
+        res.push(eqn);
+    }
+    res
+}
+
+// Parse the header and pin declarations, returning the content
+// without equations, the symbols, and the remaining statements. We
+// need to know the chip and pins to parse anything else, so errors
+// here stop the parse.
+fn parse_declarations(data: &str) -> Result<(Content, Symbols, Vec<Statement>), Error> {
+    let statements = statements(data)?;
+
+    let mut chip = None;
+    let mut sig = Vec::new();
+    let mut pin_decls = Vec::new();
+    let mut rest = Vec::new();
+    for statement in statements.into_iter() {
+        match keyword(&statement).as_deref() {
+            Some("DEVICE") => match statement.get(1) {
+                Some((line_num, Lexeme::Name(name))) => {
+                    chip = Some(at_line(*line_num, device_to_chip(name))?)
+                }
+                _ => {
+                    return parser::err(statement[0].0, ErrorCode::BadToken { expected: "device" })
+                }
+            },
+            Some("NAME") => {
+                // Use the name as the signature, like galasm.
+                if let Some((_, Lexeme::Name(name))) = statement.get(1) {
+                    sig = name.bytes().take(8).collect();
+                }
+            }
+            Some(header) if HEADERS.contains(&header) => (),
+            Some("PIN") => pin_decls.push(statement),
+            _ => rest.push(statement),
+        }
+    }
+
+    let chip = match chip {
+        Some(chip) => chip,
+        None => return parser::err(1, ErrorCode::NoDevice),
+    };
+
+    let mut decls = Vec::new();
+    for statement in pin_decls.into_iter() {
+        let end_line = last_line(&statement);
+        let line_num = statement[0].0;
+        let mut tokens = statement.into_iter().skip(1).peekable();
+        let pin_nums = parse_pin_numbers(&mut tokens, end_line)?;
+        expect(&mut tokens, end_line, '=')?;
+        let names = parse_names(&mut tokens, end_line)?;
+        expect_end(&mut tokens)?;
+        decls.push((line_num, pin_nums, names));
+    }
+    let (pins, pin_map) = parser::assign_pins(chip, decls)?;
+    let symbols = Symbols::new(chip, pin_map);
+
+    let content = Content {
+        chip,
+        sig,
+        pins: parser::pin_names(pins),
+        eqns: Vec::new(),
+        mode: None,
+        notes: Notes::default(),
+    };
+    Ok((content, symbols, rest))
+}
+
+pub fn parse_str(data: &str) -> Result<Content, Vec<Error>> {
+    let (content, symbols, rest) = parse_declarations(data).map_err(|e| vec![e])?;
+
+    let mut fields = HashMap::new();
+    let mut eqns = Vec::new();
+    let mut errors = Vec::new();
+    for statement in rest.into_iter() {
+        let end_line = last_line(&statement);
+        let res = if keyword(&statement).as_deref() == Some("FIELD") {
+            parse_field(&symbols, &mut fields, statement)
+        } else {
+            parse_equation(&symbols, &fields, statement).map(|eqn| eqns.push(eqn))
+        };
+        if let Err(e) = res {
+            errors.push(at_statement_end(e, end_line));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Content {
+        eqns: convert_equations(eqns),
+        ..content
+    })
+}
+
+// Errors found by running off the end of a statement are reported at
+// its last line.
+pub(crate) fn at_statement_end(e: Error, end_line: LineNum) -> Error {
+    if e.line == EOF_LINE {
+        Error {
+            line: end_line,
+            ..e
+        }
+    } else {
+        e
+    }
+}
+
+// Parse a 'FIELD name = [pins]' statement.
+fn parse_field(
+    symbols: &Symbols,
+    fields: &mut HashMap<String, Vec<String>>,
+    statement: Statement,
+) -> Result<(), Error> {
+    let end_line = last_line(&statement);
+    let mut tokens = statement.into_iter().skip(1).peekable();
+    let (line_num, name) = match tokens.next() {
+        Some((line_num, Lexeme::Name(name))) => (line_num, name),
+        Some((line_num, _)) => {
+            return parser::err(line_num, ErrorCode::BadToken { expected: "name" })
+        }
+        None => return parser::err(end_line, ErrorCode::BadToken { expected: "name" }),
+    };
+    expect(&mut tokens, end_line, '=')?;
+    if !matches!(tokens.peek(), Some((_, Lexeme::Punct('[')))) {
+        return parser::err(end_line, ErrorCode::BadToken { expected: "'['" });
+    }
+    let names = parse_names(&mut tokens, end_line)?;
+    expect_end(&mut tokens)?;
+
+    if symbols.pins.contains_key(&name) || fields.contains_key(&name) {
+        return parser::err(line_num, ErrorCode::RepeatedPinName { name });
+    }
+    let mut pins = Vec::new();
+    for (pin, neg) in names.into_iter() {
+        if neg {
+            return parser::err(line_num, ErrorCode::BadToken { expected: "pin" });
+        }
+        lookup(symbols, line_num, &pin)?;
+        pins.push(pin);
+    }
+    fields.insert(name, pins);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("1F").unwrap(), (0x1f, 0xff));
+        assert_eq!(parse_number("'b'01X1").unwrap(), (0b0101, 0b1101));
+        assert_eq!(parse_number("'h'X3").unwrap(), (0x03, 0x0f));
+        assert_eq!(parse_number("'d'12").unwrap(), (12, u64::MAX));
+        assert!(parse_number("'b'012").is_err());
+        assert!(parse_number("'q'1").is_err());
+    }
+
+    const COUNTER: &str = "\
+Name     Counter;
+Device   g22v10;
+
+/* Inputs */
+PIN 1 = CLK;
+PIN [2..4] = [D0..2];
+PIN 5 = RST;
+PIN 6 = EN;
+
+/* Outputs */
+PIN [14..15] = [Q0..1];
+PIN 16 = Y;
+
+FIELD DATA = [D2..0];
+
+Q0.d = DATA:5 # Q1;
+Q1.d = !Q0 & D0;
+Q0.ar = RST;
+Q1.ar = RST;
+Y = D0 $ D1;
+Y.oe = EN;
+";
+
+    fn pin(pin: usize, neg: bool) -> Expr {
+        Expr::Pin(Pin { pin, neg })
+    }
+
+    fn error_codes(data: &str) -> Vec<(LineNum, ErrorCode)> {
+        match parse_str(data) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => errors.into_iter().map(|e| (e.line, e.code)).collect(),
+        }
+    }
+
+    #[test]
+    fn design() {
+        let content = parse_str(COUNTER).unwrap();
+        assert_eq!(content.chip, Chip::GAL22V10);
+        assert_eq!(content.sig, b"Counter");
+        assert_eq!(&content.pins[..6], ["CLK", "D0", "D1", "D2", "RST", "EN"]);
+
+        let eqns = content
+            .eqns
+            .iter()
+            .map(|eqn| (eqn.lhs.clone(), eqn.rhs.clone()))
+            .collect::<Vec<_>>();
+        let output = |n, suffix| LHS::Pin((Pin { pin: n, neg: false }, suffix));
+        assert_eq!(
+            eqns,
+            vec![
+                (
+                    output(14, Suffix::R),
+                    Expr::Or(vec![
+                        Expr::And(vec![pin(4, false), pin(3, true), pin(2, false)]),
+                        pin(15, false),
+                    ])
+                ),
+                (
+                    output(15, Suffix::R),
+                    Expr::And(vec![Expr::Not(Box::new(pin(14, false))), pin(2, false)])
+                ),
+                // The repeated AR equation is dropped.
+                (LHS::Ar, pin(5, false)),
+                // Having an output enable makes Y tristate.
+                (
+                    output(16, Suffix::T),
+                    Expr::Xor(Box::new(pin(2, false)), Box::new(pin(3, false)))
+                ),
+                (output(16, Suffix::E), pin(6, false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn devices() {
+        let atf = COUNTER.replace("g22v10", "ATF22V10C");
+        assert_eq!(parse_str(&atf).unwrap().chip, Chip::ATF22V10C);
+
+        let bad = COUNTER.replace("g22v10", "g26v12");
+        assert!(matches!(
+            error_codes(&bad)[..],
+            [(2, ErrorCode::BadGALType { .. })]
+        ));
+
+        let missing = COUNTER.replace("Device   g22v10;", "");
+        assert!(matches!(
+            error_codes(&missing)[..],
+            [(1, ErrorCode::NoDevice)]
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        // Errors in equations and fields are all reported.
+        let data = COUNTER
+            .replace("[D2..0];", "[D2..0];\nFIELD Q0 = [D0];")
+            .replace("!Q0 & D0", "!Q0 & W")
+            .replace("Y.oe", "Y.xx");
+        assert!(matches!(
+            error_codes(&data)[..],
+            [
+                (15, ErrorCode::RepeatedPinName { .. }),
+                (18, ErrorCode::UnknownPin { .. }),
+                (22, ErrorCode::BadSuffix { .. }),
+            ]
+        ));
+
+        // AR and SP are only supported on the GAL22V10 and GAL20RA10.
+        let data = COUNTER
+            .replace("g22v10", "g16v8")
+            .replace("PIN 1 =", "PIN 11 =");
+        assert!(matches!(
+            error_codes(&data)[..],
+            [(18, ErrorCode::BadSuffix { .. }), ..]
+        ));
+    }
+
+    #[test]
+    fn ranges() {
+        // Ranges are capped, rather than expanded until memory runs out.
+        let data = COUNTER.replace("[2..4]", "[2..100000000]");
+        assert!(matches!(
+            error_codes(&data)[..],
+            [(6, ErrorCode::BadBusRange)]
+        ));
+        let data = COUNTER.replace("[D0..2]", "[D0..100000000]");
+        assert!(matches!(
+            error_codes(&data)[..],
+            [(6, ErrorCode::BadBusRange)]
+        ));
+    }
+
+    #[test]
+    fn detection() {
+        assert!(is_cupl("/* A design */\nName Test;\nDevice g16v8;\n"));
+        assert!(!is_cupl("GAL16V8\nTest\n"));
+    }
+}
@@ -13,28 +13,93 @@ use thiserror::Error;
 
 pub type LineNum = usize;
 
+// A place in the input: a line, and where known, the range of columns
+// on it (counting from 1, end exclusive).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Pos {
+    pub line: LineNum,
+    pub cols: Option<(usize, usize)>,
+}
+
+impl From<LineNum> for Pos {
+    fn from(line: LineNum) -> Self {
+        Pos { line, cols: None }
+    }
+}
+
+// All the errors found in a file, and any warnings found before
+// giving up.
 #[derive(Clone, Debug, Error)]
-#[error("{}: {}", file, err)]
 pub struct FileError {
     pub file: String,
-    pub err: Error,
+    pub errors: Vec<Error>,
+    pub warnings: Vec<Warning>,
 }
 
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let warnings = self.warnings.iter().map(|w| w as &dyn fmt::Display);
+        let errors = self.errors.iter().map(|e| e as &dyn fmt::Display);
+        for (i, msg) in warnings.chain(errors).enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", self.file, msg)?;
+        }
+        Ok(())
+    }
+}
+
+// Errors that aren't about a particular line, such as I/O errors, are
+// given line NO_LINE.
 #[derive(Clone, Debug, Error)]
-#[error("Error in line {}: {}", line, code)]
 pub struct Error {
     pub code: ErrorCode,
     pub line: LineNum,
+    pub cols: Option<(usize, usize)>,
+}
+
+pub const NO_LINE: LineNum = 0;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == NO_LINE {
+            write!(f, "Error: {}", self.code)
+        } else {
+            write!(f, "Error in line {}: {}", self.line, self.code)
+        }
+    }
+}
+
+impl Error {
+    // Show the line the error is on, from the given source, with the
+    // offending columns underlined, rustc-style.
+    pub fn snippet(&self, source: &str) -> Option<String> {
+        let text = source.lines().nth(self.line.checked_sub(1)?)?;
+        let margin = " ".repeat(self.line.to_string().len());
+        let mut res = format!("{} |\n{} | {}\n", margin, self.line, text);
+        if let Some((start, end)) = self.cols {
+            // Copy tabs so that the carets line up.
+            let indent = text
+                .chars()
+                .take(start - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            let carets = "^".repeat(usize::max(end - start, 1));
+            res.push_str(&format!("{} | {}{}\n", margin, indent, carets));
+        }
+        Some(res)
+    }
 }
 
 #[derive(Clone, Debug, Error)]
 pub enum ErrorCode {
+    #[error("{name} is a DEFINE, and can't be assigned to")]
+    AssignedDefine { name: String },
     #[error("GAL22V10: {term} is not allowed as pinname")]
     ReservedPinName { term: SpecialProductTerm },
     #[error("no suffix is allowed for {term}")]
     SpecialSuffix { term: SpecialProductTerm },
-    #[error("internal error: analyse_mode should never let you use this pin as an input")]
-    BadAnalysis,
     #[error("use of {term} is not allowed in equations")]
     BadSpecial { term: SpecialProductTerm },
     #[error("unexpected character in input: '{c}'")]
@@ -43,10 +108,26 @@ pub enum ErrorCode {
     BadEquationEOF,
     #[error("expected pin name, found end of line")]
     BadEOL,
+    #[error("bad bus index or range, expected something like '[7..0]'")]
+    BadBusRange,
+    #[error("pin number {pin} is out of range (1 to {max})")]
+    BadPinNumber { pin: usize, max: usize },
     #[error("unexpected GAL type found: '{gal}'")]
     BadGALType { gal: String },
     #[error("NC (Not Connected) is not allowed in logic equations")]
     BadNC,
+    #[error("bad number: '{num}'")]
+    BadNumber { num: String },
+    #[error("unexpected character in table row: '{c}'")]
+    BadTableBit { c: char },
+    #[error("unexpected character in test vector {side}s: '{c}'")]
+    BadVectorBit { side: &'static str, c: char },
+    #[error("wrong number of {side} bits in table row - expected {expected}, found {found}")]
+    BadTableRow {
+        side: &'static str,
+        expected: usize,
+        found: usize,
+    },
     #[error("wrong number of pins on pin definition line - expected {expected}, found {found}")]
     BadPinCount { found: usize, expected: usize },
     #[error("expected pin definitions, found end of file")]
@@ -57,10 +138,14 @@ pub enum ErrorCode {
     BadPower,
     #[error("expected signature, found end of file")]
     BadSigEOF,
+    #[error("bad signature '{sig}', expected text, \"quoted text\" or 0x and hex bytes")]
+    BadSignature { sig: String },
     #[error("unknown suffix found: '{suffix}'")]
     BadSuffix { suffix: String },
     #[error("expected {expected}, found other token")]
     BadToken { expected: &'static str },
+    #[error("value {value} does not fit in a {width}-bit bus")]
+    BusValueTooWide { value: u64, width: usize },
     #[error("pin {pin} must be named {name}")]
     InvalidPowerPinName { pin: usize, name: &'static str },
     #[error(
@@ -88,6 +173,8 @@ pub enum ErrorCode {
     MoreThanOneProduct,
     #[error("missing clock definition (.CLK) of registered output")]
     NoCLK,
+    #[error("no 'Device' statement found")]
+    NoDevice,
     #[error("'=' expected")]
     NoEquals,
     #[error("pin name expected after '/', found non-alphabetic character '{c}'")]
@@ -102,31 +189,281 @@ pub enum ErrorCode {
     ReservedRegisteredInput { pin: usize, name: &'static str },
     #[error("pin {pin} can't be used as input in complex mode")]
     NotAnComplexModeInput { pin: usize },
+    #[error("pin {pin} can't be used as input in simple mode")]
+    NotASimpleModeInput { pin: usize },
+    #[error("{reason}, which isn't possible in {mode} mode")]
+    ModeMismatch { mode: &'static str, reason: String },
+    #[error("MODE can only be set for the GAL16V8, GAL20V8 and their ATF equivalents")]
+    ModeNotSupported,
     #[error("this pin can't be used as output")]
     NotAnOutput,
+    #[error("can't read {file}: {err}")]
+    ReadFailed { file: String, err: String },
     #[error("{term} is defined twice")]
     RepeatedSpecial { term: SpecialProductTerm },
     #[error("multiple .{suffix} definitions for the same output")]
     RepeatedControl { suffix: OutputSuffix },
     #[error("output {name} is defined multiple times")]
     RepeatedOutput { name: String },
+    #[error("MODE is set more than once")]
+    RepeatedMode,
     #[error("pinname {name} is defined twice")]
     RepeatedPinName { name: String },
+    #[error("pin {pin} is assigned twice")]
+    RepeatedPinNumber { pin: usize },
+    #[error("pin {name} is listed more than once in VECTORS")]
+    RepeatedVectorPin { name: String },
+    #[error("state {state} is defined twice")]
+    RepeatedState { state: u64 },
     #[error("the output must be defined to use .{suffix}")]
     UndefinedOutput { suffix: OutputSuffix },
+    #[error("expression has more than {max} product terms when multiplied out")]
+    TooManyExpandedProducts { max: usize },
     #[error("too many product terms in sum for pin (max: {max}, saw: {seen})")]
     TooManyProducts { max: usize, seen: usize },
+    #[error("too many product terms in sum for pin after expanding XOR (max: {max}, saw: {seen})")]
+    TooManyXorProducts { max: usize, seen: usize },
     #[error("GAL16V8/20V8: tri. control for reg. output is not allowed")]
     TristateReg,
     #[error("unknown pinname '{name}'")]
     UnknownPin { name: String },
+    #[error("expected '}}' to end {block}, found end of file")]
+    UnterminatedBlock { block: &'static str },
+    #[error("expected ')', found end of line")]
+    UnmatchedParen,
     #[error("tristate control without previous '.T'")]
     UnmatchedTristate,
+    #[error("can't write {file}: {err}")]
+    WriteFailed { file: String, err: String },
+}
+
+impl Error {
+    fn line_opt(&self) -> Option<LineNum> {
+        Some(self.line).filter(|line| *line != NO_LINE)
+    }
+
+    // Format as 'file:line:column: error: message', like GCC.
+    pub fn to_gnu(&self, file: &str) -> String {
+        gnu_message(file, self.line_opt(), self.cols, "error", &self.code)
+    }
+
+    // Format as a one-line JSON object.
+    pub fn to_json(&self, file: &str) -> String {
+        json_message(
+            file,
+            self.line_opt(),
+            self.cols,
+            "error",
+            self.code.name(),
+            &self.code,
+        )
+    }
+}
+
+fn gnu_message(
+    file: &str,
+    line: Option<LineNum>,
+    cols: Option<(usize, usize)>,
+    severity: &str,
+    msg: &dyn fmt::Display,
+) -> String {
+    match (line, cols) {
+        (Some(line), Some((start, _))) => {
+            format!("{}:{}:{}: {}: {}", file, line, start, severity, msg)
+        }
+        (Some(line), None) => format!("{}:{}: {}: {}", file, line, severity, msg),
+        (None, _) => format!("{}: {}: {}", file, severity, msg),
+    }
+}
+
+fn json_message(
+    file: &str,
+    line: Option<LineNum>,
+    cols: Option<(usize, usize)>,
+    severity: &str,
+    code: &str,
+    msg: &dyn fmt::Display,
+) -> String {
+    let or_null = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+    format!(
+        "{{\"file\":{},\"line\":{},\"column\":{},\"end_column\":{},\
+         \"severity\":\"{}\",\"code\":{},\"message\":{}}}",
+        json_string(file),
+        or_null(line),
+        or_null(cols.map(|(start, _)| start)),
+        or_null(cols.map(|(_, end)| end)),
+        severity,
+        json_string(code),
+        json_string(&msg.to_string()),
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+impl ErrorCode {
+    // A stable name for the error, for tools to match on rather than
+    // the message text. These mustn't change, even if the variant is
+    // renamed.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::AssignedDefine { .. } => "AssignedDefine",
+            ErrorCode::ReservedPinName { .. } => "ReservedPinName",
+            ErrorCode::SpecialSuffix { .. } => "SpecialSuffix",
+            ErrorCode::BadSpecial { .. } => "BadSpecial",
+            ErrorCode::BadChar { .. } => "BadChar",
+            ErrorCode::BadEquationEOF => "BadEquationEOF",
+            ErrorCode::BadEOL => "BadEOL",
+            ErrorCode::BadBusRange => "BadBusRange",
+            ErrorCode::BadPinNumber { .. } => "BadPinNumber",
+            ErrorCode::BadGALType { .. } => "BadGALType",
+            ErrorCode::BadNC => "BadNC",
+            ErrorCode::BadNumber { .. } => "BadNumber",
+            ErrorCode::BadTableBit { .. } => "BadTableBit",
+            ErrorCode::BadVectorBit { .. } => "BadVectorBit",
+            ErrorCode::BadTableRow { .. } => "BadTableRow",
+            ErrorCode::BadPinCount { .. } => "BadPinCount",
+            ErrorCode::BadPinEOF => "BadPinEOF",
+            ErrorCode::BadPinSuffix => "BadPinSuffix",
+            ErrorCode::BadPower => "BadPower",
+            ErrorCode::BadSigEOF => "BadSigEOF",
+            ErrorCode::BadSignature { .. } => "BadSignature",
+            ErrorCode::BadSuffix { .. } => "BadSuffix",
+            ErrorCode::BadToken { .. } => "BadToken",
+            ErrorCode::BusValueTooWide { .. } => "BusValueTooWide",
+            ErrorCode::InvalidPowerPinName { .. } => "InvalidPowerPinName",
+            ErrorCode::InvalidPowerPinLocation { .. } => "InvalidPowerPinLocation",
+            ErrorCode::DisallowedControl { .. } => "DisallowedControl",
+            ErrorCode::InvalidControl { .. } => "InvalidControl",
+            ErrorCode::InvertedSpecial { .. } => "InvertedSpecial",
+            ErrorCode::InvertedControl { .. } => "InvertedControl",
+            ErrorCode::InvertedPower { .. } => "InvertedPower",
+            ErrorCode::MoreThanOneProduct => "MoreThanOneProduct",
+            ErrorCode::NoCLK => "NoCLK",
+            ErrorCode::NoDevice => "NoDevice",
+            ErrorCode::NoEquals => "NoEquals",
+            ErrorCode::NoPinName { .. } => "NoPinName",
+            ErrorCode::NoPinNameEOL => "NoPinNameEOL",
+            ErrorCode::ReservedInputGAL20RA10 { .. } => "ReservedInputGAL20RA10",
+            ErrorCode::ReservedRegisteredInput { .. } => "ReservedRegisteredInput",
+            ErrorCode::NotAnComplexModeInput { .. } => "NotAnComplexModeInput",
+            ErrorCode::NotASimpleModeInput { .. } => "NotASimpleModeInput",
+            ErrorCode::ModeMismatch { .. } => "ModeMismatch",
+            ErrorCode::ModeNotSupported => "ModeNotSupported",
+            ErrorCode::NotAnOutput => "NotAnOutput",
+            ErrorCode::ReadFailed { .. } => "ReadFailed",
+            ErrorCode::RepeatedSpecial { .. } => "RepeatedSpecial",
+            ErrorCode::RepeatedControl { .. } => "RepeatedControl",
+            ErrorCode::RepeatedOutput { .. } => "RepeatedOutput",
+            ErrorCode::RepeatedMode => "RepeatedMode",
+            ErrorCode::RepeatedPinName { .. } => "RepeatedPinName",
+            ErrorCode::RepeatedPinNumber { .. } => "RepeatedPinNumber",
+            ErrorCode::RepeatedVectorPin { .. } => "RepeatedVectorPin",
+            ErrorCode::RepeatedState { .. } => "RepeatedState",
+            ErrorCode::UndefinedOutput { .. } => "UndefinedOutput",
+            ErrorCode::TooManyExpandedProducts { .. } => "TooManyExpandedProducts",
+            ErrorCode::TooManyProducts { .. } => "TooManyProducts",
+            ErrorCode::TooManyXorProducts { .. } => "TooManyXorProducts",
+            ErrorCode::TristateReg => "TristateReg",
+            ErrorCode::UnknownPin { .. } => "UnknownPin",
+            ErrorCode::UnterminatedBlock { .. } => "UnterminatedBlock",
+            ErrorCode::UnmatchedParen => "UnmatchedParen",
+            ErrorCode::UnmatchedTristate => "UnmatchedTristate",
+            ErrorCode::WriteFailed { .. } => "WriteFailed",
+        }
+    }
+}
+
+// Warnings are for designs that are legal, but probably not what was
+// meant. Some are about the file as a whole, rather than a line.
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub line: Option<LineNum>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Warning in line {}: {}", line, self.code),
+            None => write!(f, "Warning: {}", self.code),
+        }
+    }
+}
+
+impl Warning {
+    pub fn to_gnu(&self, file: &str) -> String {
+        gnu_message(file, self.line, None, "warning", &self.code)
+    }
+
+    pub fn to_json(&self, file: &str) -> String {
+        json_message(
+            file,
+            self.line,
+            None,
+            "warning",
+            self.code.name(),
+            &self.code,
+        )
+    }
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum WarningCode {
+    #[error("product term is always false, as it uses a pin both negated and not")]
+    AlwaysFalseProduct,
+    #[error("product term is repeated")]
+    DuplicateProduct,
+    #[error("output pin {name} has no equation, so is only used as an input")]
+    UndrivenFeedback { name: String },
+    #[error("pin {name} is never used")]
+    UnusedPin { name: String },
+    #[error("output pin {name} has no equation, and is never used as an input")]
+    UnusedOutput { name: String },
+    #[error("signature is {len} bytes long, only the first 8 are used")]
+    SignatureTruncated { len: usize },
+}
+
+impl WarningCode {
+    // A stable name for the warning, like ErrorCode::name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningCode::AlwaysFalseProduct => "AlwaysFalseProduct",
+            WarningCode::DuplicateProduct => "DuplicateProduct",
+            WarningCode::UndrivenFeedback { .. } => "UndrivenFeedback",
+            WarningCode::UnusedPin { .. } => "UnusedPin",
+            WarningCode::UnusedOutput { .. } => "UnusedOutput",
+            WarningCode::SignatureTruncated { .. } => "SignatureTruncated",
+        }
+    }
 }
 
 // Adapt an ErrorCode to an Error.
-pub fn at_line<Val>(line: LineNum, res: Result<Val, ErrorCode>) -> Result<Val, Error> {
-    res.map_err(|e| Error { code: e, line })
+pub fn at_line<Val, P: Into<Pos>>(pos: P, res: Result<Val, ErrorCode>) -> Result<Val, Error> {
+    let pos = pos.into();
+    res.map_err(|e| Error {
+        code: e,
+        line: pos.line,
+        cols: pos.cols,
+    })
+}
+
+// Record the error, if any, so that we can carry on and report all the
+// errors together.
+pub fn note<Val>(errors: &mut Vec<Error>, res: Result<Val, Error>) -> Option<Val> {
+    res.map_err(|e| errors.push(e)).ok()
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -174,3 +511,31 @@ impl fmt::Display for SpecialProductTerm {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_formats() {
+        let e = Error {
+            code: ErrorCode::UnknownPin {
+                name: "FOO".to_string(),
+            },
+            line: 7,
+            cols: Some((11, 14)),
+        };
+        assert_eq!(e.code.name(), "UnknownPin");
+        assert_eq!(
+            e.to_gnu("a.pld"),
+            "a.pld:7:11: error: unknown pinname 'FOO'"
+        );
+        assert_eq!(
+            e.to_json("a\"b.pld"),
+            "{\"file\":\"a\\\"b.pld\",\"line\":7,\"column\":11,\"end_column\":14,\
+             \"severity\":\"error\",\"code\":\"UnknownPin\",\
+             \"message\":\"unknown pinname 'FOO'\"}"
+        );
+        assert_eq!(ErrorCode::BadNC.name(), "BadNC");
+    }
+}
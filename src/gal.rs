@@ -32,6 +32,10 @@ pub struct Term {
     // Each inner Vec represents an AND term. The overall term is the
     // OR of the inner terms.
     pub pins: Vec<Vec<Pin>>,
+    // Set if the products came from multiplying out XORs, so that we
+    // can explain where an unexpectedly large number of products
+    // came from.
+    pub xor_expanded: bool,
 }
 
 // The 'GAL' struct represents the fuse state of the GAL that we're
@@ -60,11 +64,29 @@ pub enum Mode {
     Registered,
 }
 
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "simple" => Some(Mode::Simple),
+            "complex" => Some(Mode::Complex),
+            "registered" => Some(Mode::Registered),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Simple => "simple",
+            Mode::Complex => "complex",
+            Mode::Registered => "registered",
+        }
+    }
+}
+
 // Map input pin number to column within the fuse table. The mappings
 // depend on the mode settings for the GALxxV8s, so they're here rather
 // than in chips.rs.
 
-const BAD: Result<i32, ErrorCode> = Err(ErrorCode::BadAnalysis);
 const PWR: Result<i32, ErrorCode> = Err(ErrorCode::BadPower);
 
 const REG_P1: Result<i32, ErrorCode> = Err(ErrorCode::ReservedRegisteredInput {
@@ -80,6 +102,12 @@ const REG_P13: Result<i32, ErrorCode> = Err(ErrorCode::ReservedRegisteredInput {
     name: "/OE",
 });
 
+// Only reachable if the mode's been forced, as analyse_mode avoids them.
+const SMPL_P15: Result<i32, ErrorCode> = Err(ErrorCode::NotASimpleModeInput { pin: 15 });
+const SMPL_P16: Result<i32, ErrorCode> = Err(ErrorCode::NotASimpleModeInput { pin: 16 });
+const SMPL_P18: Result<i32, ErrorCode> = Err(ErrorCode::NotASimpleModeInput { pin: 18 });
+const SMPL_P19: Result<i32, ErrorCode> = Err(ErrorCode::NotASimpleModeInput { pin: 19 });
+
 const CPLX_P12: Result<i32, ErrorCode> = Err(ErrorCode::NotAnComplexModeInput { pin: 12 });
 const CPLX_P15: Result<i32, ErrorCode> = Err(ErrorCode::NotAnComplexModeInput { pin: 15 });
 const CPLX_P19: Result<i32, ErrorCode> = Err(ErrorCode::NotAnComplexModeInput { pin: 19 });
@@ -97,8 +125,8 @@ const P13_20RA10: Result<i32, ErrorCode> = Err(ErrorCode::ReservedInputGAL20RA10
 // GAL16V8
 #[rustfmt::skip]
 const PIN_TO_COL_16_SIMPLE: [Result<i32, ErrorCode>; 20] = [
-    Ok(2),  Ok(0),  Ok(4),  Ok(8),  Ok(12), Ok(16), Ok(20), Ok(24), Ok(28), PWR,
-    Ok(30), Ok(26), Ok(22), Ok(18), BAD,    BAD,    Ok(14), Ok(10), Ok(6),  PWR,
+    Ok(2),  Ok(0),  Ok(4),  Ok(8),  Ok(12),   Ok(16),   Ok(20), Ok(24), Ok(28), PWR,
+    Ok(30), Ok(26), Ok(22), Ok(18), SMPL_P15, SMPL_P16, Ok(14), Ok(10), Ok(6),  PWR,
 ];
 #[rustfmt::skip]
 const PIN_TO_COL_16_COMPLEX: [Result<i32, ErrorCode>; 20] = [
@@ -114,8 +142,8 @@ const PIN_TO_COL_16_REGISTERED: [Result<i32, ErrorCode>; 20] = [
 // GAL20V8
 #[rustfmt::skip]
 const PIN_TO_COL_20_SIMPLE: [Result<i32, ErrorCode>; 24] = [
-    Ok(2),  Ok(0),  Ok(4),  Ok(8),  Ok(12), Ok(16), Ok(20), Ok(24), Ok(28), Ok(32), Ok(36), PWR,
-    Ok(38), Ok(34), Ok(30), Ok(26), Ok(22), BAD,    BAD,    Ok(18), Ok(14), Ok(10), Ok(6),  PWR,
+    Ok(2),  Ok(0),  Ok(4),  Ok(8),  Ok(12), Ok(16),   Ok(20),   Ok(24), Ok(28), Ok(32), Ok(36), PWR,
+    Ok(38), Ok(34), Ok(30), Ok(26), Ok(22), SMPL_P18, SMPL_P19, Ok(18), Ok(14), Ok(10), Ok(6),  PWR,
 ];
 #[rustfmt::skip]
 const PIN_TO_COL_20_COMPLEX: [Result<i32, ErrorCode>; 24] = [
@@ -163,7 +191,7 @@ impl GAL {
 
     // Set the fuses associated with mode for GALxxV8s.
     pub fn set_mode(&mut self, mode: Mode) {
-        assert!(self.chip == Chip::GAL16V8 || self.chip == Chip::GAL20V8);
+        assert!(self.chip.family() == Chip::GAL16V8 || self.chip.family() == Chip::GAL20V8);
         match mode {
             Mode::Simple => {
                 self.syn = true;
@@ -182,7 +210,7 @@ impl GAL {
 
     // Retrive the mode from the mode fuses.
     pub fn get_mode(&self) -> Mode {
-        assert!(self.chip == Chip::GAL16V8 || self.chip == Chip::GAL20V8);
+        assert!(self.chip.family() == Chip::GAL16V8 || self.chip.family() == Chip::GAL20V8);
         match (self.syn, self.ac0) {
             (true, false) => Mode::Simple,
             (true, true) => Mode::Complex,
@@ -199,7 +227,7 @@ impl GAL {
     // only inverts the output in active low mode. Hence, in active
     // high mode we must flip the negation.
     fn needs_flip(&self, pin_num: usize) -> bool {
-        if self.chip != Chip::GAL22V10 {
+        if self.chip.family() != Chip::GAL22V10 {
             return false;
         }
 
@@ -224,6 +252,11 @@ impl GAL {
                     term.line_num,
                     Err(if single_row {
                         ErrorCode::MoreThanOneProduct
+                    } else if term.xor_expanded {
+                        ErrorCode::TooManyXorProducts {
+                            max: bounds.max_row - 1,
+                            seen: term.pins.len(),
+                        }
                     } else {
                         ErrorCode::TooManyProducts {
                             max: bounds.max_row - 1,
@@ -277,17 +310,17 @@ impl GAL {
     // Map the input pin number to the fuse column number.
     fn pin_to_column(&self, pin_num: usize) -> Result<usize, ErrorCode> {
         let column_lookup: &[Result<i32, ErrorCode>] = match self.chip {
-            Chip::GAL16V8 => match self.get_mode() {
+            Chip::GAL16V8 | Chip::ATF16V8B => match self.get_mode() {
                 Mode::Simple => &PIN_TO_COL_16_SIMPLE,
                 Mode::Complex => &PIN_TO_COL_16_COMPLEX,
                 Mode::Registered => &PIN_TO_COL_16_REGISTERED,
             },
-            Chip::GAL20V8 => match self.get_mode() {
+            Chip::GAL20V8 | Chip::ATF20V8B => match self.get_mode() {
                 Mode::Simple => &PIN_TO_COL_20_SIMPLE,
                 Mode::Complex => &PIN_TO_COL_20_COMPLEX,
                 Mode::Registered => &PIN_TO_COL_20_REGISTERED,
             },
-            Chip::GAL22V10 => &PIN_TO_COL_22V10,
+            Chip::GAL22V10 | Chip::ATF22V10C => &PIN_TO_COL_22V10,
             Chip::GAL20RA10 => &PIN_TO_COL_20RA10,
         };
 
@@ -313,6 +346,7 @@ pub fn true_term(line_num: LineNum) -> Term {
     Term {
         line_num,
         pins: vec![Vec::new()],
+        xor_expanded: false,
     }
 }
 
@@ -321,5 +355,6 @@ pub fn false_term(line_num: LineNum) -> Term {
     Term {
         line_num,
         pins: Vec::new(),
+        xor_expanded: false,
     }
 }
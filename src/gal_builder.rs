@@ -8,19 +8,32 @@
 use crate::{
     blueprint::{Active, Blueprint, PinMode, OLMC},
     chips::Chip,
-    errors::{at_line, Error, ErrorCode, OutputSuffix},
+    errors::{at_line, note, Error, ErrorCode, OutputSuffix, NO_LINE},
     gal::{self, Bounds, Mode, GAL},
+    minimise,
 };
 
-pub fn build(blueprint: &Blueprint) -> Result<GAL, Error> {
+// Build the GAL, carrying on past errors so that they're all
+// reported, in line order. 'flip_polarity' allows outputs to be
+// inverted to make them fit, which galasm doesn't do.
+pub fn build(blueprint: &Blueprint, flip_polarity: bool) -> Result<GAL, Vec<Error>> {
     let mut gal = GAL::new(blueprint.chip);
+    let mut errors = Vec::new();
 
     match gal.chip {
-        Chip::GAL16V8 | Chip::GAL20V8 => build_galxv8(&mut gal, blueprint)?,
-        Chip::GAL22V10 => build_gal22v10(&mut gal, blueprint)?,
-        Chip::GAL20RA10 => build_gal20ra10(&mut gal, blueprint)?,
+        Chip::GAL16V8 | Chip::GAL20V8 | Chip::ATF16V8B | Chip::ATF20V8B => {
+            build_galxv8(&mut gal, blueprint, flip_polarity, &mut errors)
+        }
+        Chip::GAL22V10 | Chip::ATF22V10C => {
+            build_gal22v10(&mut gal, blueprint, flip_polarity, &mut errors)
+        }
+        Chip::GAL20RA10 => build_gal20ra10(&mut gal, blueprint, flip_polarity, &mut errors),
     }
 
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| e.line);
+        return Err(errors);
+    }
     Ok(gal)
 }
 
@@ -28,22 +41,35 @@ pub fn build(blueprint: &Blueprint) -> Result<GAL, Error> {
 // Chip-specific GAL-building algorithms.
 //
 
-fn build_galxv8(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
-    check_not_gal20ra10(blueprint)?;
+fn build_galxv8(
+    gal: &mut GAL,
+    blueprint: &Blueprint,
+    flip_polarity: bool,
+    errors: &mut Vec<Error>,
+) {
+    check_not_gal20ra10(blueprint, errors);
     set_sig(gal, blueprint);
-    set_mode(gal, blueprint);
+    set_mode(gal, blueprint, errors);
+    // Needs the mode to be set, to know how many rows are available.
+    let blueprint = &fit_polarity(gal, blueprint, flip_polarity);
     // Are we implementing combinatorial expressions as tristate?
     // Pure combinatorial is only available in simple mode.
     let com_is_tri = gal.get_mode() != Mode::Simple;
     set_tristate(gal, blueprint, com_is_tri);
     set_xors(gal, blueprint);
-    set_core_eqns(gal, blueprint)?;
+    set_core_eqns(gal, blueprint, errors);
     set_pts(gal);
-    Ok(())
 }
 
-fn build_gal22v10(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
-    check_not_gal20ra10(blueprint)?;
+fn build_gal22v10(
+    gal: &mut GAL,
+    blueprint: &Blueprint,
+    flip_polarity: bool,
+    errors: &mut Vec<Error>,
+) {
+    check_no_mode(blueprint, errors);
+    check_not_gal20ra10(blueprint, errors);
+    let blueprint = &fit_polarity(gal, blueprint, flip_polarity);
     set_sig(gal, blueprint);
     // NB: Needs to be called before the set_eqns, since the set_and
     // logic depends on it.
@@ -52,17 +78,22 @@ fn build_gal22v10(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
     set_tristate(gal, blueprint, true);
     // Must come before core_eqns, for "needs_flip".
     set_xors(gal, blueprint);
-    set_core_eqns(gal, blueprint)?;
-    set_arsp_eqns(gal, blueprint)?;
-    Ok(())
+    set_core_eqns(gal, blueprint, errors);
+    set_arsp_eqns(gal, blueprint, errors);
 }
 
-fn build_gal20ra10(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
+fn build_gal20ra10(
+    gal: &mut GAL,
+    blueprint: &Blueprint,
+    flip_polarity: bool,
+    errors: &mut Vec<Error>,
+) {
+    check_no_mode(blueprint, errors);
+    let blueprint = &fit_polarity(gal, blueprint, flip_polarity);
     set_sig(gal, blueprint);
     set_xors(gal, blueprint);
-    set_core_eqns(gal, blueprint)?;
-    set_aux_eqns(gal, blueprint)?;
-    Ok(())
+    set_core_eqns(gal, blueprint, errors);
+    set_aux_eqns(gal, blueprint, errors);
 }
 
 ////////////////////////////////////////////////////////////////////////
@@ -102,43 +133,49 @@ fn set_tristate(gal: &mut GAL, blueprint: &Blueprint, com_is_tri: bool) {
 }
 
 // Set the main equation and tristate enable equation.
-fn set_core_eqns(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
+fn set_core_eqns(gal: &mut GAL, blueprint: &Blueprint, errors: &mut Vec<Error>) {
     for (olmc, i) in blueprint.olmcs.iter().zip(0..) {
         let bounds = gal.chip.get_bounds(i);
 
         match &olmc.output {
             Some((_, term)) => {
                 let bounds = adjust_main_bounds(gal, &olmc.output, &bounds);
-                gal.add_term(term, &bounds)?;
+                note(errors, gal.add_term(term, &bounds));
+            }
+            None => {
+                note(errors, gal.add_term(&gal::false_term(0), &bounds));
             }
-            None => gal.add_term(&gal::false_term(0), &bounds)?,
         }
 
         if let Some(term) = &olmc.tri_con {
-            at_line(term.line_num, check_tristate(gal.chip, olmc))?;
-            gal.add_term(
-                term,
-                &Bounds {
-                    row_offset: 0,
-                    max_row: 1,
-                    ..bounds
-                },
-            )?;
+            note(
+                errors,
+                at_line(term.line_num, check_tristate(gal.chip, olmc)),
+            );
+            note(
+                errors,
+                gal.add_term(
+                    term,
+                    &Bounds {
+                        row_offset: 0,
+                        max_row: 1,
+                        ..bounds
+                    },
+                ),
+            );
         }
     }
-
-    Ok(())
 }
 
 // Set the AR and SP equations, unique to the GAL22V10.
-fn set_arsp_eqns(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
+fn set_arsp_eqns(gal: &mut GAL, blueprint: &Blueprint, errors: &mut Vec<Error>) {
     // AR
     let ar_bounds = Bounds {
         start_row: 0,
         max_row: 1,
         row_offset: 0,
     };
-    gal.add_term_opt(&blueprint.ar, &ar_bounds)?;
+    note(errors, gal.add_term_opt(&blueprint.ar, &ar_bounds));
 
     // SP
     let sp_bounds = Bounds {
@@ -146,19 +183,17 @@ fn set_arsp_eqns(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
         max_row: 1,
         row_offset: 0,
     };
-    gal.add_term_opt(&blueprint.sp, &sp_bounds)?;
-
-    Ok(())
+    note(errors, gal.add_term_opt(&blueprint.sp, &sp_bounds));
 }
 
 // Set ARST, APRST and CLK, only used by GAL20RA10.
-fn set_aux_eqns(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
+fn set_aux_eqns(gal: &mut GAL, blueprint: &Blueprint, errors: &mut Vec<Error>) {
     for (olmc, i) in blueprint.olmcs.iter().zip(0..) {
         let bounds = gal.chip.get_bounds(i);
 
-        check_aux(&olmc.clock, olmc, OutputSuffix::CLK)?;
-        check_aux(&olmc.arst, olmc, OutputSuffix::ARST)?;
-        check_aux(&olmc.aprst, olmc, OutputSuffix::APRST)?;
+        note(errors, check_aux(&olmc.clock, olmc, OutputSuffix::CLK));
+        note(errors, check_aux(&olmc.arst, olmc, OutputSuffix::ARST));
+        note(errors, check_aux(&olmc.aprst, olmc, OutputSuffix::APRST));
 
         if let Some((PinMode::Registered, ref term)) = olmc.output {
             let arst_bounds = Bounds {
@@ -166,17 +201,20 @@ fn set_aux_eqns(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
                 max_row: 3,
                 ..bounds
             };
-            gal.add_term_opt(&olmc.arst, &arst_bounds)?;
+            note(errors, gal.add_term_opt(&olmc.arst, &arst_bounds));
 
             let aprst_bounds = Bounds {
                 row_offset: 3,
                 max_row: 4,
                 ..bounds
             };
-            gal.add_term_opt(&olmc.aprst, &aprst_bounds)?;
+            note(errors, gal.add_term_opt(&olmc.aprst, &aprst_bounds));
 
             if olmc.clock.is_none() {
-                return at_line(term.line_num, Err(ErrorCode::NoCLK));
+                note(
+                    errors,
+                    at_line(term.line_num, Err::<(), _>(ErrorCode::NoCLK)),
+                );
             }
         }
 
@@ -187,11 +225,9 @@ fn set_aux_eqns(gal: &mut GAL, blueprint: &Blueprint) -> Result<(), Error> {
                 max_row: 2,
                 ..bounds
             };
-            gal.add_term_opt(&olmc.clock, &clock_bounds)?;
+            note(errors, gal.add_term_opt(&olmc.clock, &clock_bounds));
         }
     }
-
-    Ok(())
 }
 
 // Set the XOR bits for inverting outputs, if necessary.
@@ -215,11 +251,44 @@ fn set_pts(gal: &mut GAL) {
 // Other helper functions.
 //
 
+// If an output has more products than will fit in its OLMC, see if
+// the complement fits, and implement that with the output polarity
+// flipped instead. We only do this for combinatorial and tristate
+// outputs, as flipping a registered output would change the value
+// it resets to. Nothing is changed unless 'enabled'.
+fn fit_polarity(gal: &GAL, blueprint: &Blueprint, enabled: bool) -> Blueprint {
+    let mut blueprint = blueprint.clone();
+    if !enabled {
+        return blueprint;
+    }
+    for (olmc, i) in blueprint.olmcs.iter_mut().zip(0..) {
+        let bounds = adjust_main_bounds(gal, &olmc.output, &gal.chip.get_bounds(i));
+        let num_rows = bounds.max_row - bounds.row_offset;
+
+        if let Some((mode, term)) = &mut olmc.output {
+            if *mode == PinMode::Registered || term.pins.len() <= num_rows {
+                continue;
+            }
+
+            if let Some(pins) = minimise::complement(&term.pins) {
+                if pins.len() <= num_rows {
+                    term.pins = pins;
+                    olmc.active = match olmc.active {
+                        Active::Low => Active::High,
+                        Active::High => Active::Low,
+                    };
+                }
+            }
+        }
+    }
+    blueprint
+}
+
 // Adjust the bounds for the main term of there's a tristate enable
 // term etc. in the first rows.
 fn adjust_main_bounds(gal: &GAL, output: &Option<(PinMode, gal::Term)>, bounds: &Bounds) -> Bounds {
     match gal.chip {
-        Chip::GAL16V8 | Chip::GAL20V8 => {
+        Chip::GAL16V8 | Chip::GAL20V8 | Chip::ATF16V8B | Chip::ATF20V8B => {
             // Registered outputs don't have a tristate enable, or
             // indeed any pins in simple mode.
             let reg_out = matches!(output, Some((PinMode::Registered, _)));
@@ -234,7 +303,7 @@ fn adjust_main_bounds(gal: &GAL, output: &Option<(PinMode, gal::Term)>, bounds:
             }
         }
         // Skip tristate enable.
-        Chip::GAL22V10 => Bounds {
+        Chip::GAL22V10 | Chip::ATF22V10C => Bounds {
             row_offset: 1,
             ..*bounds
         },
@@ -247,34 +316,34 @@ fn adjust_main_bounds(gal: &GAL, output: &Option<(PinMode, gal::Term)>, bounds:
 }
 
 // Check that we're not trying to use GAL20RA10-specific features.
-fn check_not_gal20ra10(blueprint: &Blueprint) -> Result<(), Error> {
+fn check_not_gal20ra10(blueprint: &Blueprint, errors: &mut Vec<Error>) {
     for olmc in blueprint.olmcs.iter() {
-        if let Some(term) = &olmc.clock {
-            return at_line(
-                term.line_num,
-                Err(ErrorCode::DisallowedControl {
-                    suffix: OutputSuffix::CLK,
-                }),
-            );
-        }
-        if let Some(term) = &olmc.arst {
-            return at_line(
-                term.line_num,
-                Err(ErrorCode::DisallowedControl {
-                    suffix: OutputSuffix::ARST,
-                }),
-            );
-        }
-        if let Some(term) = &olmc.aprst {
-            return at_line(
-                term.line_num,
-                Err(ErrorCode::DisallowedControl {
-                    suffix: OutputSuffix::APRST,
-                }),
-            );
+        for (field, suffix) in [
+            (&olmc.clock, OutputSuffix::CLK),
+            (&olmc.arst, OutputSuffix::ARST),
+            (&olmc.aprst, OutputSuffix::APRST),
+        ] {
+            if let Some(term) = field {
+                note(
+                    errors,
+                    at_line(
+                        term.line_num,
+                        Err::<(), _>(ErrorCode::DisallowedControl { suffix }),
+                    ),
+                );
+            }
         }
     }
-    Ok(())
+}
+
+// Only the GALxV8s have modes to force.
+fn check_no_mode(blueprint: &Blueprint, errors: &mut Vec<Error>) {
+    if blueprint.mode.is_some() {
+        note(
+            errors,
+            at_line(NO_LINE, Err::<(), _>(ErrorCode::ModeNotSupported)),
+        );
+    }
 }
 
 // Check that the main output is in the right mode to use a tristate.
@@ -283,7 +352,9 @@ fn check_tristate(chip: Chip, olmc: &OLMC) -> Result<(), ErrorCode> {
         None => Err(ErrorCode::UndefinedOutput {
             suffix: OutputSuffix::E,
         }),
-        Some((PinMode::Registered, _)) if chip == Chip::GAL16V8 || chip == Chip::GAL20V8 => {
+        Some((PinMode::Registered, _))
+            if chip.family() == Chip::GAL16V8 || chip.family() == Chip::GAL20V8 =>
+        {
             Err(ErrorCode::TristateReg)
         }
         Some((PinMode::Combinatorial, _)) => Err(ErrorCode::UnmatchedTristate),
@@ -309,31 +380,76 @@ fn check_aux(field: &Option<gal::Term>, olmc: &OLMC, suffix: OutputSuffix) -> Re
 ////////////////////////////////////////////////////////////////////////
 // GALxV8 analysis - determine which mode to run the chip in.
 
-fn set_mode(gal: &mut GAL, blueprint: &Blueprint) {
-    gal.set_mode(analyse_mode(&blueprint.olmcs));
+fn set_mode(gal: &mut GAL, blueprint: &Blueprint, errors: &mut Vec<Error>) {
+    let mode = match blueprint.mode {
+        Some(mode) => {
+            check_mode(gal.chip, &blueprint.olmcs, mode, errors);
+            mode
+        }
+        None => analyse_mode(gal.chip, &blueprint.olmcs).0,
+    };
+    gal.set_mode(mode);
+}
+
+// Check that the OLMCs can be implemented in a forced mode. This
+// covers the outputs - inputs the mode can't provide are caught when
+// the equations are built.
+fn check_mode(chip: Chip, olmcs: &[OLMC], mode: Mode, errors: &mut Vec<Error>) {
+    for (n, olmc) in olmcs.iter().enumerate() {
+        let (pin_mode, term) = match &olmc.output {
+            Some((pin_mode, term)) => (pin_mode, term),
+            None => continue,
+        };
+        let pin = chip.olmc_to_pin(n);
+        let reason = match (pin_mode, mode) {
+            (PinMode::Registered, Mode::Simple | Mode::Complex) => {
+                format!("pin {} is a registered output", pin)
+            }
+            (PinMode::Tristate, Mode::Simple) => format!("pin {} is a tristate output", pin),
+            (_, Mode::Simple) if olmc.feedback => {
+                format!("output pin {} is also used as an input", pin)
+            }
+            _ => continue,
+        };
+        note(
+            errors,
+            at_line(
+                term.line_num,
+                Err::<(), _>(ErrorCode::ModeMismatch {
+                    mode: mode.name(),
+                    reason,
+                }),
+            ),
+        );
+    }
 }
 
-fn analyse_mode(olmcs: &[OLMC]) -> Mode {
+// Choose the mode, returning the reason for the choice as well.
+fn analyse_mode(chip: Chip, olmcs: &[OLMC]) -> (Mode, String) {
     assert_eq!(
         olmcs.len(),
         8,
         "analyse_mode must only be called for devices with 8 OLMCs"
     );
+    let pin = |n| chip.olmc_to_pin(n);
 
     // If there's a registered pin, it's registered mode.
-    if olmcs
+    if let Some(n) = olmcs
         .iter()
-        .any(|olmc| matches!(olmc.output, Some((PinMode::Registered, _))))
+        .position(|olmc| matches!(olmc.output, Some((PinMode::Registered, _))))
     {
-        return Mode::Registered;
+        return (Mode::Registered, format!("because pin {} uses .R", pin(n)));
     }
 
     // If there's a tristate, it's complex mode.
-    if olmcs
+    if let Some(n) = olmcs
         .iter()
-        .any(|olmc| matches!(olmc.output, Some((PinMode::Tristate, _))))
+        .position(|olmc| matches!(olmc.output, Some((PinMode::Tristate, _))))
     {
-        return Mode::Complex;
+        return (
+            Mode::Complex,
+            format!("because pin {} is a tristate output", pin(n)),
+        );
     }
 
     // If we can't use simple mode, use complex mode.
@@ -342,16 +458,79 @@ fn analyse_mode(olmcs: &[OLMC]) -> Mode {
             // Some OLMCs cannot be configured as pure inputs in simple mode.
             None => {
                 if n == 3 || n == 4 {
-                    return Mode::Complex;
+                    return (
+                        Mode::Complex,
+                        format!(
+                            "because pin {} is used as an input, which simple mode \
+                             can't provide on pins {} and {}",
+                            pin(n),
+                            pin(3),
+                            pin(4)
+                        ),
+                    );
                 }
             }
             // OLMC pins cannot be used as combinatorial feedback in simple mode.
-            Some(_) => return Mode::Complex,
+            Some(_) => {
+                return (
+                    Mode::Complex,
+                    format!(
+                        "because output pin {} is also used as an input, which \
+                         simple mode can't provide",
+                        pin(n)
+                    ),
+                )
+            }
         }
     }
 
     // If there is still no mode defined, use simple mode.
-    Mode::Simple
+    (
+        Mode::Simple,
+        "as no outputs are registered, tristate or used as inputs".to_string(),
+    )
+}
+
+// The pins that can't be used freely in the given mode.
+fn reserved_pins(chip: Chip, mode: Mode) -> Vec<(usize, &'static str)> {
+    let no_input = "can't be used as an input";
+    match mode {
+        Mode::Simple => vec![
+            (chip.olmc_to_pin(3), no_input),
+            (chip.olmc_to_pin(4), no_input),
+        ],
+        Mode::Complex => vec![
+            (chip.olmc_to_pin(0), no_input),
+            (chip.last_olmc(), no_input),
+        ],
+        Mode::Registered => vec![
+            (1, "is reserved for the clock"),
+            (chip.num_pins() / 2 + 1, "is reserved for /OE"),
+        ],
+    }
+}
+
+// Explain which mode a GALxV8 is put in and why, and which pins that
+// leaves unavailable, as errors about those pins can be baffling
+// otherwise. Returns None for other chips.
+pub fn explain_mode(blueprint: &Blueprint) -> Option<String> {
+    let chip = blueprint.chip;
+    if chip.family() != Chip::GAL16V8 && chip.family() != Chip::GAL20V8 {
+        return None;
+    }
+    let (mode, reason) = match blueprint.mode {
+        Some(mode) => (mode, "as it was set with MODE or --mode".to_string()),
+        None => analyse_mode(chip, &blueprint.olmcs),
+    };
+
+    let mut res = format!("{} mode: {}, {}\n", chip.name(), mode.name(), reason);
+    for (pin, what) in reserved_pins(chip, mode) {
+        res.push_str(&format!("  pin {} {}\n", pin, what));
+    }
+    if mode == Mode::Simple {
+        res.push_str("  outputs can't also be used as inputs\n");
+    }
+    Some(res)
 }
 
 #[cfg(test)]
@@ -367,6 +546,7 @@ mod tests {
                 Term {
                     line_num: 0,
                     pins: vec![],
+                    xor_expanded: false,
                 },
             )),
             active: Active::Low,
@@ -409,7 +589,7 @@ mod tests {
             olmc(PinMode::Combinatorial),
             olmc(PinMode::Combinatorial),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Simple);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Simple);
     }
 
     #[test]
@@ -424,7 +604,7 @@ mod tests {
             olmc(PinMode::Tristate),
             olmc(PinMode::Combinatorial),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Complex);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Complex);
     }
 
     #[test]
@@ -439,7 +619,7 @@ mod tests {
             olmc(PinMode::Combinatorial),
             olmc(PinMode::Combinatorial),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Complex);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Complex);
     }
 
     #[test]
@@ -454,7 +634,7 @@ mod tests {
             olmc(PinMode::Combinatorial),
             olmc(PinMode::Combinatorial),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Complex);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Complex);
     }
 
     #[test]
@@ -469,7 +649,7 @@ mod tests {
             olmc_feedback_and_output(),
             olmc(PinMode::Combinatorial),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Complex);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Complex);
     }
 
     #[test]
@@ -484,7 +664,7 @@ mod tests {
             olmc(PinMode::Registered),
             olmc(PinMode::Registered),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Registered);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Registered);
     }
 
     #[test]
@@ -499,6 +679,61 @@ mod tests {
             olmc(PinMode::Registered),
             olmc(PinMode::Registered),
         ];
-        assert_eq!(analyse_mode(&olmcs), Mode::Registered);
+        assert_eq!(analyse_mode(Chip::GAL16V8, &olmcs).0, Mode::Registered);
+    }
+
+    #[test]
+    fn mode_reasons() {
+        let mut olmcs = vec![olmc(PinMode::Combinatorial); 8];
+        olmcs[4] = olmc_feedback_no_output();
+        assert_eq!(
+            analyse_mode(Chip::GAL20V8, &olmcs).1,
+            "because pin 19 is used as an input, which simple mode can't provide on pins 18 and 19"
+        );
+        olmcs[2] = olmc(PinMode::Registered);
+        assert_eq!(
+            analyse_mode(Chip::GAL16V8, &olmcs).1,
+            "because pin 14 uses .R"
+        );
+        assert_eq!(
+            reserved_pins(Chip::GAL20V8, Mode::Registered),
+            vec![
+                (1, "is reserved for the clock"),
+                (13, "is reserved for /OE")
+            ]
+        );
+    }
+
+    fn mode_errors(olmcs: &[OLMC], mode: Mode) -> Vec<String> {
+        let mut errors = Vec::new();
+        check_mode(Chip::GAL16V8, olmcs, mode, &mut errors);
+        errors.iter().map(|e| e.code.to_string()).collect()
+    }
+
+    #[test]
+    fn forced_mode() {
+        let olmcs = [
+            olmc(PinMode::Combinatorial),
+            olmc(PinMode::Combinatorial),
+            olmc(PinMode::Combinatorial),
+            olmc(PinMode::Combinatorial),
+            olmc(PinMode::Combinatorial),
+            olmc(PinMode::Tristate),
+            olmc_feedback_and_output(),
+            olmc(PinMode::Registered),
+        ];
+        assert_eq!(
+            mode_errors(&olmcs, Mode::Simple),
+            vec![
+                "pin 17 is a tristate output, which isn't possible in simple mode",
+                "output pin 18 is also used as an input, which isn't possible in simple mode",
+                "pin 19 is a registered output, which isn't possible in simple mode",
+            ]
+        );
+        assert_eq!(
+            mode_errors(&olmcs, Mode::Complex),
+            vec!["pin 19 is a registered output, which isn't possible in complex mode"]
+        );
+        assert!(mode_errors(&olmcs, Mode::Registered).is_empty());
     }
 }
@@ -6,32 +6,314 @@
 // programmed into the GAL in order to make it implement those
 // equations.
 //
-// The galette binary is a thin wrapper around "assemble", but if you
-// want to programmatically generate GAL assembly files, you should be
-// able to use the publicly exposed members of the library, starting
-// from a parser::Content or a blueprint::Blueprint, depending on what
-// you want to start with.
+// The galette binary is a thin wrapper around "assemble". To assemble
+// without touching the file system, "assemble_str" takes the input as
+// a string and returns the generated files' contents. If you want to
+// programmatically generate GAL assembly files, you should be able to
+// use the publicly exposed members of the library, starting from a
+// parser::Content or a blueprint::Blueprint, depending on what you
+// want to start with.
 //
 
+use std::path::Path;
+
+pub mod abel;
 pub mod blueprint;
 pub mod chips;
+pub mod cupl;
 pub mod errors;
 pub mod gal;
 pub mod gal_builder;
+pub mod minimise;
+pub mod palasm;
 pub mod parser;
+pub mod warnings;
 pub mod writer;
 
-pub fn assemble(file_name: &str, config: &writer::Config) -> Result<(), errors::FileError> {
-    (|| {
-        let content = parser::parse(file_name)?;
-        let blueprint = blueprint::Blueprint::from(&content)?;
-        let gal = gal_builder::build(&blueprint)?;
-        writer::write_files(file_name, config, &blueprint.pins, &blueprint.olmcs, &gal).unwrap();
+// Options for the assembly process as a whole. Options that only
+// affect the generated files live in writer::Config.
+#[derive(Debug)]
+pub struct Config {
+    pub minimise: bool,
+    // If None, the format is guessed from the file.
+    pub format: Option<Format>,
+    // Fail, without writing any files, if there are warnings.
+    pub warnings_as_errors: bool,
+    // If set, replaces the signature given in the file.
+    pub signature: Option<Vec<u8>>,
+    // If set, forces the GALxV8 mode, like a MODE line in the file.
+    pub mode: Option<gal::Mode>,
+    pub writer: writer::Config,
+}
+
+// The supported input file formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Galasm,
+    Cupl,
+    Palasm,
+    Abel,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "galasm" => Some(Format::Galasm),
+            "cupl" => Some(Format::Cupl),
+            "palasm" => Some(Format::Palasm),
+            "abel" => Some(Format::Abel),
+            _ => None,
+        }
+    }
+
+    // PALASM and ABEL files have their own extensions. CUPL files
+    // share '.pld' with galasm, so we look at the contents.
+    pub fn guess(file_name: &str, data: &str) -> Format {
+        let ext = Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match ext.as_deref() {
+            Some("pds") => Format::Palasm,
+            Some("abl") => Format::Abel,
+            _ if cupl::is_cupl(data) => Format::Cupl,
+            _ => Format::Galasm,
+        }
+    }
+}
 
-        Ok(())
+// The results of assembly: the generated files, and any warnings.
+#[derive(Clone, Debug)]
+pub struct Assembled {
+    pub files: writer::Files,
+    pub warnings: Vec<errors::Warning>,
+}
+
+// Assemble the given file, writing out the results alongside it.
+pub fn assemble(
+    file_name: &str,
+    config: &Config,
+) -> Result<Vec<errors::Warning>, errors::FileError> {
+    let file_error = |errors, warnings| errors::FileError {
+        file: file_name.into(),
+        errors,
+        warnings,
+    };
+
+    let data = parser::read_input(file_name).map_err(|e| file_error(vec![e], Vec::new()))?;
+    let assembled = assemble_str(file_name, &data, config)?;
+    writer::write_files(file_name, &config.writer, &assembled.files).map_err(|(file, e)| {
+        let e = errors::Error {
+            code: errors::ErrorCode::WriteFailed {
+                file,
+                err: e.to_string(),
+            },
+            line: errors::NO_LINE,
+            cols: None,
+        };
+        file_error(vec![e], assembled.warnings.clone())
+    })?;
+
+    Ok(assembled.warnings)
+}
+
+// Assemble the input text, returning the results rather than writing
+// any files. The file name is only used for guessing the input format
+// (if not given in the config) and in errors.
+//
+// Each stage reports all the errors it finds, but we don't go on to
+// the next stage with broken input, to avoid knock-on errors. Warnings
+// are returned on success, and alongside any errors.
+pub fn assemble_str(
+    file_name: &str,
+    data: &str,
+    config: &Config,
+) -> Result<Assembled, errors::FileError> {
+    let mut warnings = Vec::new();
+    (|| {
+        let content = parse_content(file_name, data, config)?;
+        let mut blueprint = blueprint::Blueprint::from(&content)?;
+        // Check before minimising, which removes redundant products.
+        warnings = warnings::check(&content, &blueprint);
+        if config.warnings_as_errors && !warnings.is_empty() {
+            return Err(Vec::new());
+        }
+        if config.minimise {
+            blueprint.minimise();
+        } else {
+            // For galasm-identical output, leave out the DESCRIPTION
+            // and comments, which galasm drops.
+            blueprint.notes.description.clear();
+            blueprint.notes.comments.clear();
+        }
+        let gal = gal_builder::build(&blueprint, config.minimise)?;
+        Ok(writer::make_files(&config.writer, &blueprint, &gal))
     })()
-    .map_err(|err| errors::FileError {
+    .map(|files| Assembled {
+        files,
+        warnings: warnings.clone(),
+    })
+    .map_err(|errors| errors::FileError {
         file: file_name.into(),
-        err,
+        errors,
+        warnings: warnings.clone(),
     })
 }
+
+// Explain how the mode of a GALxV8 is chosen, as far as we can without
+// assembling. Returns None for other chips, or if the input has
+// errors, which assembling will report.
+pub fn explain_mode(file_name: &str, config: &Config) -> Option<String> {
+    let data = parser::read_input(file_name).ok()?;
+    let content = parse_content(file_name, &data, config).ok()?;
+    let blueprint = blueprint::Blueprint::from(&content).ok()?;
+    gal_builder::explain_mode(&blueprint)
+}
+
+// Parse the input in whatever format it's in, and apply the overrides
+// from the config.
+fn parse_content(
+    file_name: &str,
+    data: &str,
+    config: &Config,
+) -> Result<parser::Content, Vec<errors::Error>> {
+    let format = config
+        .format
+        .unwrap_or_else(|| Format::guess(file_name, data));
+    let mut content = match format {
+        Format::Galasm => parser::parse_str(data)?,
+        Format::Cupl => cupl::parse_str(data)?,
+        Format::Palasm => palasm::parse_str(data)?,
+        Format::Abel => abel::parse_str(data)?,
+    };
+    if let Some(sig) = &config.signature {
+        content.sig = sig.clone();
+    }
+    if config.mode.is_some() {
+        content.mode = config.mode;
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::WarningCode;
+
+    fn config() -> Config {
+        Config {
+            minimise: true,
+            format: None,
+            warnings_as_errors: false,
+            signature: None,
+            mode: None,
+            writer: writer::Config {
+                gen_fuse: true,
+                gen_chip: true,
+                gen_pin: true,
+                jedec_sec_bit: false,
+            },
+        }
+    }
+
+    #[test]
+    fn in_memory() {
+        let data = "GAL16V8\nSig\n\
+                    A B C D E F G H I GND\n\
+                    J Y0 Y1 Y2 Y3 Y4 Y5 Y6 Y7 VCC\n\
+                    Y0 = A * B\n";
+        let assembled = assemble_str("test.pld", data, &config()).unwrap();
+        assert!(assembled.files.jedec.contains("*QF2194"));
+        assert!(assembled.files.pin.contains("Y0"));
+        // The unconnected inputs and outputs are warned about.
+        let warnings = assembled
+            .warnings
+            .iter()
+            .map(|warning| match &warning.code {
+                WarningCode::UnusedPin { name } => ("UnusedPin", name.as_str()),
+                WarningCode::UnusedOutput { name } => ("UnusedOutput", name.as_str()),
+                code => panic!("unexpected warning: {}", code),
+            })
+            .collect::<Vec<_>>();
+        let mut expected = ["C", "D", "E", "F", "G", "H", "I", "J"]
+            .iter()
+            .map(|name| ("UnusedPin", *name))
+            .collect::<Vec<_>>();
+        expected.extend(
+            ["Y1", "Y2", "Y3", "Y4", "Y5", "Y6", "Y7"]
+                .iter()
+                .map(|name| ("UnusedOutput", *name)),
+        );
+        assert_eq!(warnings, expected);
+
+        let e = assemble_str("test.pld", &data.replace("* B", "* Z"), &config()).unwrap_err();
+        assert_eq!(e.errors.len(), 1);
+        assert_eq!(e.errors[0].line, 5);
+    }
+
+    #[test]
+    fn galasm_notes() {
+        let data = "GAL16V8\nSig\n\
+                    A B C D E F G H I GND\n\
+                    J K L M N O P Q R VCC\n\
+                    R = A * B ; AND\n\
+                    DESCRIPTION\n\
+                    A test.\n";
+        let assembled = assemble_str("test.pld", data, &config()).unwrap();
+        assert!(assembled.files.jedec.contains("A test."));
+        assert!(assembled.files.fuse.contains("; AND"));
+
+        // galasm drops the DESCRIPTION and comments.
+        let config = Config {
+            minimise: false,
+            ..config()
+        };
+        let assembled = assemble_str("test.pld", data, &config).unwrap();
+        assert!(!assembled.files.jedec.contains("A test."));
+        assert!(!assembled.files.fuse.contains("; AND"));
+    }
+
+    #[test]
+    fn polarity() {
+        // Nine products don't fit in R's OLMC, but their complement
+        // does, so R is implemented inverted.
+        let data = "GAL16V8\nSig\n\
+                    A B C D E F G H I GND\n\
+                    J K L M N O P Q R VCC\n\
+                    R = A + B + C + D + E + F + G + H + I\n";
+        let assembled = assemble_str("test.pld", data, &config()).unwrap();
+        assert!(assembled
+            .files
+            .fuse
+            .contains("Pin 19 = R            XOR = 0"));
+
+        // Without minimisation, we do what galasm does, and fail.
+        let config = Config {
+            minimise: false,
+            ..config()
+        };
+        let e = assemble_str("test.pld", data, &config).unwrap_err();
+        assert_eq!(e.errors.len(), 1);
+        assert_eq!(e.errors[0].code.name(), "TooManyProducts");
+    }
+
+    #[test]
+    fn atf_parts() {
+        let data = "GAL22V10\nSig\n\
+                    A B C D E F G H I J K GND\n\
+                    L M N O P Q R S T U V VCC\n\
+                    V = A * B\n";
+        let gal = assemble_str("test.pld", data, &config()).unwrap();
+        let atf = assemble_str(
+            "test.pld",
+            &data.replace("GAL", "ATF").replace("V10", "V10C"),
+            &config(),
+        )
+        .unwrap();
+        assert!(atf.files.jedec.contains("Device:         ATF22V10C"));
+        assert!(atf.files.jedec.contains("*QF5893\n"));
+        assert!(atf.files.jedec.contains("*L5892 0\n"));
+        // Otherwise, the fuses are the same.
+        assert_eq!(gal.files.fuse, atf.files.fuse);
+    }
+}
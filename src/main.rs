@@ -11,9 +11,16 @@ extern crate galette;
 
 use clap::{App, Arg};
 
-use std::process;
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    process,
+};
 
-use galette::writer;
+use galette::{
+    errors::{FileError, Warning},
+    gal, parser, writer,
+};
 
 fn main() {
     let matches = App::new("Galette")
@@ -54,19 +61,149 @@ fn main() {
                 .takes_value(false)
                 .help("Disable .pin file output"),
         )
+        .arg(
+            Arg::with_name("nominimise")
+                .short("m")
+                .long("nominimise")
+                .takes_value(false)
+                .help("Disable logic minimisation, for galasm-identical output"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["galasm", "cupl", "palasm", "abel"])
+                .help("Input format (default: from the file extension and contents)"),
+        )
+        .arg(
+            Arg::with_name("warnings")
+                .short("W")
+                .takes_value(true)
+                .possible_values(&["all", "none", "error"])
+                .default_value("all")
+                .help("Show warnings (-Wall), hide them (-Wnone), or fail on them (-Werror)"),
+        )
+        .arg(
+            Arg::with_name("message-format")
+                .long("message-format")
+                .takes_value(true)
+                .possible_values(&["human", "json", "gnu"])
+                .default_value("human")
+                .help("Error message format: human-readable, JSON lines, or GCC-style"),
+        )
+        .arg(
+            Arg::with_name("mode")
+                .long("mode")
+                .takes_value(true)
+                .possible_values(&["simple", "complex", "registered"])
+                .help(
+                    "GAL16V8/GAL20V8 mode to use, like a MODE line (default: from the equations)",
+                ),
+        )
+        .arg(
+            Arg::with_name("explain-mode")
+                .long("explain-mode")
+                .takes_value(false)
+                .help("Explain how the GAL16V8/GAL20V8 mode was chosen, and the pins it reserves"),
+        )
+        .arg(
+            Arg::with_name("signature")
+                .long("signature")
+                .takes_value(true)
+                .validator(|sig| {
+                    parser::parse_signature_value(&sig)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help("Signature to use instead of the file's: text, or 0x and hex bytes"),
+        )
         .get_matches();
 
     let file_name = matches.value_of("INPUT.pld").unwrap();
 
-    let config = writer::Config {
-        gen_fuse: !matches.is_present("nofuse"),
-        gen_chip: !matches.is_present("nochip"),
-        gen_pin: !matches.is_present("nopin"),
-        jedec_sec_bit: matches.is_present("secure"),
+    let config = galette::Config {
+        minimise: !matches.is_present("nominimise"),
+        format: matches
+            .value_of("format")
+            .and_then(galette::Format::from_name),
+        warnings_as_errors: matches.value_of("warnings") == Some("error"),
+        signature: matches
+            .value_of("signature")
+            .map(|sig| parser::parse_signature_value(sig).unwrap()),
+        mode: matches.value_of("mode").and_then(gal::Mode::from_name),
+        writer: writer::Config {
+            gen_fuse: !matches.is_present("nofuse"),
+            gen_chip: !matches.is_present("nochip"),
+            gen_pin: !matches.is_present("nopin"),
+            jedec_sec_bit: matches.is_present("secure"),
+        },
     };
 
-    if let Err(e) = galette::assemble(file_name, &config) {
-        eprintln!("{}", e);
-        process::exit(1);
+    let message_format = matches.value_of("message-format");
+    let show_warnings = matches.value_of("warnings") != Some("none");
+    if matches.is_present("explain-mode") {
+        if let Some(explanation) = galette::explain_mode(file_name, &config) {
+            print!("{}", explanation);
+        }
+    }
+    match galette::assemble(file_name, &config) {
+        Ok(warnings) => {
+            if show_warnings {
+                print_warnings(message_format, file_name, &warnings);
+            }
+        }
+        Err(e) => {
+            if show_warnings {
+                print_warnings(message_format, &e.file, &e.warnings);
+            }
+            print_errors(message_format, &e);
+            if config.warnings_as_errors && e.errors.is_empty() {
+                eprintln!("{}: warnings are being treated as errors", e.file);
+            }
+            process::exit(1);
+        }
+    }
+}
+
+fn print_warnings(message_format: Option<&str>, file: &str, warnings: &[Warning]) {
+    for warning in warnings.iter() {
+        match message_format {
+            // JSON goes to stdout, as it's for other programs.
+            Some("json") => println!("{}", warning.to_json(file)),
+            Some("gnu") => eprintln!("{}", warning.to_gnu(file)),
+            _ => eprintln!("{}: {}", file, warning),
+        }
+    }
+}
+
+fn print_errors(message_format: Option<&str>, e: &FileError) {
+    match message_format {
+        Some("json") => {
+            for err in e.errors.iter() {
+                println!("{}", err.to_json(&e.file));
+            }
+        }
+        Some("gnu") => {
+            for err in e.errors.iter() {
+                eprintln!("{}", err.to_gnu(&e.file));
+            }
+        }
+        _ => print_human(e),
+    }
+}
+
+fn print_human(e: &FileError) {
+    // When a person is reading, show the offending source lines too.
+    // Otherwise stick to galasm-style one-line messages.
+    let source = if io::stderr().is_terminal() {
+        fs::read_to_string(&e.file).ok()
+    } else {
+        None
+    };
+    for err in e.errors.iter() {
+        eprintln!("{}: {}", e.file, err);
+        if let Some(snippet) = source.as_ref().and_then(|s| err.snippet(s)) {
+            eprint!("{}", snippet);
+        }
     }
 }
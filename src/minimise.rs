@@ -0,0 +1,321 @@
+//
+// minimise.rs: Logic minimisation
+//
+// Reduce a sum of products to fewer, smaller products, so that more
+// designs fit in the limited number of rows each OLMC has. This is a
+// cut-down Espresso: we throw away products that are obviously
+// redundant, expand each product as far as it will go without
+// changing the function, and then drop any products that are covered
+// by the rest.
+//
+// Products are kept in their original order where possible, so that
+// equations that are already minimal come out unchanged.
+//
+
+use std::collections::HashMap;
+
+use crate::gal::Pin;
+
+type Product = Vec<Pin>;
+
+// Complementing a function, or multiplying out an expression, can
+// blow up exponentially. If we find ourselves juggling more than this
+// many products, we give up.
+pub const MAX_PRODUCTS: usize = 512;
+
+pub fn minimise(products: &[Product]) -> Vec<Product> {
+    minimise_with_dont_cares(products, &[])
+}
+
+// Minimise, where we don't care what the result is for the inputs
+// covered by 'dont_cares', so they can be used to make the products
+// bigger.
+pub fn minimise_with_dont_cares(products: &[Product], dont_cares: &[Product]) -> Vec<Product> {
+    let mut cover = simplify(products);
+    expand(&mut cover, dont_cares);
+    irredundant(&mut cover, dont_cares);
+    cover
+}
+
+// Find the minimised complement of a sum of products, or None if it
+// gets too big.
+pub fn complement(products: &[Product]) -> Option<Vec<Product>> {
+    // /(P1 + P2 + ...) = /P1 * /P2 * ..., where each /Pn is a sum of
+    // negated inputs. Multiply these out one at a time, simplifying
+    // as we go to keep the size down. We start with true, the empty
+    // product.
+    let mut res = vec![Vec::new()];
+    for product in simplify(products).iter() {
+        let mut next = Vec::new();
+        for partial in res.iter() {
+            for input in product.iter() {
+                let mut extended = partial.clone();
+                extended.push(Pin {
+                    pin: input.pin,
+                    neg: !input.neg,
+                });
+                next.push(extended);
+            }
+        }
+
+        res = simplify(&next);
+        if res.len() > MAX_PRODUCTS {
+            return None;
+        }
+    }
+
+    Some(minimise(&res))
+}
+
+// Find the parts of 'products' not covered by 'other', or None if the
+// complement of 'other' gets too big.
+pub fn subtract(products: &[Product], other: &[Product]) -> Option<Vec<Product>> {
+    let not_other = complement(other)?;
+    let mut res = Vec::new();
+    for product in products.iter() {
+        for not_product in not_other.iter() {
+            res.push([&product[..], &not_product[..]].concat());
+        }
+    }
+    Some(simplify(&res))
+}
+
+// Remove products that are always false (such as A * /A), repeated
+// inputs within a product, and products that are duplicates of, or
+// absorbed by, other products (A * B + A = A).
+pub fn simplify(products: &[Product]) -> Vec<Product> {
+    let products = products
+        .iter()
+        .filter_map(|product| clean_product(product))
+        .collect::<Vec<_>>();
+
+    let mut res = Vec::new();
+    for (i, product) in products.iter().enumerate() {
+        // Of a set of identical products, we keep the first.
+        let absorbed = products.iter().enumerate().any(|(j, other)| {
+            j != i && implies(product, other) && (j < i || !implies(other, product))
+        });
+        if !absorbed {
+            res.push(product.clone());
+        }
+    }
+    res
+}
+
+// Remove repeated inputs from a product, returning None if the
+// product can never be true.
+fn clean_product(product: &[Pin]) -> Option<Product> {
+    let mut res: Product = Vec::new();
+    for input in product.iter() {
+        if res.iter().any(|p| p.pin == input.pin && p.neg != input.neg) {
+            return None;
+        }
+        if !res.contains(input) {
+            res.push(*input);
+        }
+    }
+    Some(res)
+}
+
+// True if 'product' being true means 'other' is true - i.e. 'product'
+// contains all the inputs of 'other'.
+fn implies(product: &[Pin], other: &[Pin]) -> bool {
+    other.iter().all(|input| product.contains(input))
+}
+
+// Try to remove inputs from each product, keeping the change if the
+// bigger product is still covered by the overall function (plus
+// don't cares).
+fn expand(cover: &mut Vec<Product>, dont_cares: &[Product]) {
+    for i in 0..cover.len() {
+        let mut j = 0;
+        while j < cover[i].len() {
+            let mut candidate = cover[i].clone();
+            candidate.remove(j);
+            let full = [&cover[..], dont_cares].concat();
+            if covers(&full, &candidate) {
+                cover[i] = candidate;
+            } else {
+                j += 1;
+            }
+        }
+    }
+
+    // Expanded products may now absorb others.
+    *cover = simplify(cover);
+}
+
+// Remove products that are covered by the remaining products (plus
+// don't cares), working from the end so that earlier products are
+// preferred.
+fn irredundant(cover: &mut Vec<Product>, dont_cares: &[Product]) {
+    for i in (0..cover.len()).rev() {
+        let product = cover.remove(i);
+        let rest = [&cover[..], dont_cares].concat();
+        if !covers(&rest, &product) {
+            cover.insert(i, product);
+        }
+    }
+}
+
+// Is the product entirely covered by the sum of products? This is
+// the case iff the cover, restricted to where the product is true, is
+// always true.
+fn covers(cover: &[Product], product: &[Pin]) -> bool {
+    let restricted = product
+        .iter()
+        .fold(cover.to_vec(), |cover, input| cofactor(&cover, *input));
+    is_tautology(&restricted)
+}
+
+// Restrict the cover to the case where the given input is true.
+fn cofactor(cover: &[Product], input: Pin) -> Vec<Product> {
+    cover
+        .iter()
+        .filter(|product| {
+            !product
+                .iter()
+                .any(|p| p.pin == input.pin && p.neg != input.neg)
+        })
+        .map(|product| product.iter().filter(|p| **p != input).cloned().collect())
+        .collect()
+}
+
+fn is_tautology(cover: &[Product]) -> bool {
+    // The empty product is always true.
+    if cover.iter().any(|product| product.is_empty()) {
+        return true;
+    }
+
+    // Count the uses of each input, in each polarity.
+    let mut uses: HashMap<usize, (usize, usize)> = HashMap::new();
+    for input in cover.iter().flatten() {
+        let entry = uses.entry(input.pin).or_insert((0, 0));
+        if input.neg {
+            entry.1 += 1;
+        } else {
+            entry.0 += 1;
+        }
+    }
+
+    // If no input appears in both polarities, the cover can only be
+    // a tautology by containing an empty product, which we've
+    // already checked for. This also catches the empty cover.
+    let split = uses
+        .iter()
+        .filter(|(_, (pos, neg))| *pos > 0 && *neg > 0)
+        .max_by_key(|(pin, (pos, neg))| (pos + neg, std::cmp::Reverse(**pin)))
+        .map(|(pin, _)| *pin);
+
+    match split {
+        None => false,
+        Some(pin) => {
+            is_tautology(&cofactor(cover, Pin { pin, neg: false }))
+                && is_tautology(&cofactor(cover, Pin { pin, neg: true }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(pin: usize) -> Pin {
+        Pin { pin, neg: false }
+    }
+
+    fn n(pin: usize) -> Pin {
+        Pin { pin, neg: true }
+    }
+
+    #[test]
+    fn already_minimal() {
+        let products = vec![vec![p(1), p(2)], vec![n(3)], vec![p(4), n(1)]];
+        assert_eq!(minimise(&products), products);
+    }
+
+    #[test]
+    fn contradiction() {
+        let products = vec![vec![p(1), n(1)], vec![p(2), p(2)]];
+        assert_eq!(minimise(&products), vec![vec![p(2)]]);
+    }
+
+    #[test]
+    fn absorption() {
+        let products = vec![vec![p(1), p(2)], vec![p(3)], vec![p(1)], vec![p(1)]];
+        assert_eq!(simplify(&products), vec![vec![p(3)], vec![p(1)]]);
+    }
+
+    #[test]
+    fn merge_adjacent() {
+        // A * B + A * /B = A
+        let products = vec![vec![p(1), p(2)], vec![p(1), n(2)]];
+        assert_eq!(minimise(&products), vec![vec![p(1)]]);
+    }
+
+    #[test]
+    fn reduce_input() {
+        // A + /A * B = A + B
+        let products = vec![vec![p(1)], vec![n(1), p(2)]];
+        assert_eq!(minimise(&products), vec![vec![p(1)], vec![p(2)]]);
+    }
+
+    #[test]
+    fn consensus() {
+        // A * B + /A * C + B * C = A * B + /A * C
+        let products = vec![vec![p(1), p(2)], vec![n(1), p(3)], vec![p(2), p(3)]];
+        assert_eq!(
+            minimise(&products),
+            vec![vec![p(1), p(2)], vec![n(1), p(3)]]
+        );
+    }
+
+    #[test]
+    fn dont_cares() {
+        // A * B, with A * /B a don't care = A
+        let products = vec![vec![p(1), p(2)]];
+        let dont_cares = vec![vec![p(1), n(2)]];
+        assert_eq!(
+            minimise_with_dont_cares(&products, &dont_cares),
+            vec![vec![p(1)]]
+        );
+    }
+
+    #[test]
+    fn complement_products() {
+        // /(A * B + /C) = /A * C + /B * C
+        let products = vec![vec![p(1), p(2)], vec![n(3)]];
+        assert_eq!(
+            complement(&products),
+            Some(vec![vec![n(1), p(3)], vec![n(2), p(3)]])
+        );
+
+        // /(A + /A) = false, /false = true
+        assert_eq!(complement(&[vec![p(1)], vec![n(1)]]), Some(vec![]));
+        assert_eq!(complement(&[]), Some(vec![vec![]]));
+    }
+
+    #[test]
+    fn subtract_products() {
+        // A - A * B = A * /B
+        assert_eq!(
+            subtract(&[vec![p(1)]], &[vec![p(1), p(2)]]),
+            Some(vec![vec![p(1), n(2)]])
+        );
+        // A - B = A * /B, A - A = false, A - false = A
+        assert_eq!(
+            subtract(&[vec![p(1)]], &[vec![p(2)]]),
+            Some(vec![vec![p(1), n(2)]])
+        );
+        assert_eq!(subtract(&[vec![p(1)]], &[vec![p(1)]]), Some(vec![]));
+        assert_eq!(subtract(&[vec![p(1)]], &[]), Some(vec![vec![p(1)]]));
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(minimise(&[]), Vec::<Product>::new());
+        assert_eq!(minimise(&[vec![]]), vec![Vec::<Pin>::new()]);
+        // A + /A = true
+        assert_eq!(minimise(&[vec![p(1)], vec![n(1)]]), vec![Vec::<Pin>::new()]);
+    }
+}
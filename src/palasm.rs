@@ -0,0 +1,280 @@
+//
+// palasm.rs: PALASM input parser
+//
+// Read the equation subset of PALASM's .pds format, and convert it to
+// the same 'Content' structure as the galasm-style parser produces.
+// The file has header lines ('TITLE', 'PATTERN' etc.), a 'CHIP' line
+// naming the part, a freeform list of pins, and then the equations
+// after 'EQUATIONS', up to an optional 'SIMULATION' section.
+//
+// galasm's input format descends from PALASM, so the equations are
+// nearly the same. We rewrite the few differences ('Q := ...' for
+// registered outputs, ':+:' for XOR, '.TRST' for output enables, etc.)
+// and pass the lines, with their original line numbers, through the
+// galasm parser.
+//
+
+use crate::{
+    chips::Chip,
+    cupl,
+    errors::{at_line, Error, ErrorCode, LineNum},
+    parser::{self, Content},
+};
+
+fn remove_comment(s: &str) -> &str {
+    match s.find(';') {
+        Some(i) => &s[..i],
+        None => s,
+    }
+}
+
+// Does the line start with the given keyword? Keywords are
+// case-insensitive.
+fn is_keyword(line: &str, keyword: &str) -> bool {
+    line.split_whitespace()
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case(keyword))
+}
+
+// Convert a PALASM equation line into galasm syntax.
+fn rewrite_line(line: &str) -> String {
+    // Registered outputs are assigned with ':='.
+    let line = match line.split_once(":=") {
+        Some((lhs, rhs)) => format!("{}.R ={}", lhs.trim_end(), rhs),
+        None => line.to_string(),
+    };
+    let line = line.replace(":+:", "$").replace(":*:", "!$");
+
+    // Map the suffixes across.
+    let mut res = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        res.push(c);
+        if c == '.' {
+            let mut ext = String::new();
+            while let Some(c) = chars.peek().cloned().filter(char::is_ascii_alphanumeric) {
+                chars.next();
+                ext.push(c);
+            }
+            let ext = ext.to_ascii_uppercase();
+            res.push_str(match ext.as_str() {
+                "TRST" => "E",
+                "CLKF" => "CLK",
+                "RSTF" => "ARST",
+                "SETF" => "APRST",
+                _ => &ext,
+            });
+        }
+    }
+
+    // The GAL22V10's shared reset and preset are written against
+    // 'GLOBAL'.
+    let trimmed = res.trim_start();
+    if let Some(rest) = trimmed.strip_prefix("GLOBAL.ARST") {
+        format!("AR{}", rest)
+    } else if let Some(rest) = trimmed.strip_prefix("GLOBAL.APRST") {
+        format!("SP{}", rest)
+    } else {
+        res
+    }
+}
+
+pub fn parse_str(data: &str) -> Result<Content, Vec<Error>> {
+    let (last_line, galasm) = to_galasm(data).map_err(|e| vec![e])?;
+
+    let content = parser::parse_core(galasm.iter().map(|(i, line)| (*i, line.as_str()))).map_err(
+        |errors| {
+            errors
+                .into_iter()
+                // Columns are of the rewritten line, so drop them.
+                .map(|e| Error {
+                    cols: None,
+                    ..cupl::at_statement_end(e, last_line)
+                })
+                .collect::<Vec<_>>()
+        },
+    )?;
+
+    Ok(Content {
+        eqns: cupl::convert_equations(content.eqns),
+        ..content
+    })
+}
+
+// Read the header and pins, and build the galasm input, keeping the
+// line numbers of the equations. Also returns the number of lines.
+fn to_galasm(data: &str) -> Result<(LineNum, Vec<(LineNum, String)>), Error> {
+    let last_line = data.lines().count();
+    let mut lines = (1..)
+        .zip(data.lines())
+        .map(|(i, line)| (i, remove_comment(line).trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    // Header lines, up to the 'CHIP' line. We use the 'PATTERN' as
+    // the signature.
+    let mut sig = String::new();
+    let (chip_line, chip) = loop {
+        match lines.next() {
+            Some((line_num, line)) if is_keyword(line, "CHIP") => {
+                let device = line.split_whitespace().nth(2).unwrap_or("");
+                break (line_num, at_line(line_num, Chip::from_pal_name(device))?);
+            }
+            Some((_, line)) if is_keyword(line, "PATTERN") => {
+                sig = line
+                    .split_whitespace()
+                    .skip(1)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+            }
+            Some(_) => (),
+            None => return parser::err(last_line, ErrorCode::NoDevice),
+        }
+    };
+
+    // Pins, in order, up to the 'EQUATIONS' line.
+    let mut pins = Vec::new();
+    for (_, line) in lines.by_ref() {
+        if is_keyword(line, "EQUATIONS") {
+            break;
+        }
+        pins.extend(line.split_whitespace().map(str::to_string));
+    }
+    let num_pins = chip.num_pins();
+    if pins.len() != num_pins {
+        return parser::err(
+            chip_line,
+            ErrorCode::BadPinCount {
+                found: pins.len(),
+                expected: num_pins,
+            },
+        );
+    }
+
+    // Build galasm input, keeping the line numbers of the equations.
+    let sig = sig.chars().take(8).collect::<String>();
+    let mut galasm = vec![
+        (chip_line, chip.name().to_string()),
+        (chip_line, sig),
+        (chip_line, pins[..num_pins / 2].join(" ")),
+        (chip_line, pins[num_pins / 2..].join(" ")),
+    ];
+    galasm.extend(
+        lines
+            .take_while(|(_, line)| !is_keyword(line, "SIMULATION"))
+            .map(|(line_num, line)| (line_num, rewrite_line(line))),
+    );
+
+    Ok((last_line, galasm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gal::Pin,
+        parser::{Expr, Suffix, LHS},
+    };
+
+    const COUNTER: &str = "\
+TITLE    Counter
+PATTERN  CNT1
+REVISION 1
+CHIP     CNT1 PAL16R4
+
+; Pins 1 to 20
+CLK A  B  C  NC NC NC NC NC GND
+/OE NC NC Q0 Q1 NC NC O1 NC VCC
+
+EQUATIONS
+
+Q0 := /Q0 * A
+Q1 := Q0 :+: Q1  ; Toggle
+O1 = A * B
+   + C
+O1.TRST = /B
+
+SIMULATION
+TRACE_ON A B
+";
+
+    fn pin(pin: usize, neg: bool) -> Expr {
+        Expr::Pin(Pin { pin, neg })
+    }
+
+    #[test]
+    fn design() {
+        let content = match parse_str(COUNTER) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        assert_eq!(content.chip, Chip::GAL16V8);
+        assert_eq!(content.sig, b"CNT1");
+
+        let eqns = content
+            .eqns
+            .iter()
+            .map(|eqn| (eqn.line_num, eqn.lhs.clone(), eqn.rhs.clone()))
+            .collect::<Vec<_>>();
+        let output = |n, suffix| LHS::Pin((Pin { pin: n, neg: false }, suffix));
+        assert_eq!(
+            eqns,
+            vec![
+                (
+                    12,
+                    output(14, Suffix::R),
+                    Expr::And(vec![pin(14, true), pin(2, false)])
+                ),
+                (
+                    13,
+                    output(15, Suffix::R),
+                    Expr::Xor(Box::new(pin(14, false)), Box::new(pin(15, false)))
+                ),
+                // The output enable makes O1 tristate.
+                (
+                    14,
+                    output(18, Suffix::T),
+                    Expr::Or(vec![
+                        Expr::And(vec![pin(2, false), pin(3, false)]),
+                        pin(4, false)
+                    ])
+                ),
+                (16, output(18, Suffix::E), pin(3, true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let data = COUNTER.replace("PAL16R4", "PAL32R16");
+        match parse_str(&data) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => assert!(matches!(
+                errors[..],
+                [Error {
+                    code: ErrorCode::BadGALType { .. },
+                    line: 4,
+                    ..
+                }]
+            )),
+        }
+
+        // Errors are reported against the original lines.
+        let data = COUNTER
+            .replace("/Q0 * A", "/Q0 * Z")
+            .replace("= /B", "= /Y");
+        match parse_str(&data) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => {
+                let lines = errors.iter().map(|e| e.line).collect::<Vec<_>>();
+                assert_eq!(lines, vec![12, 16]);
+            }
+        }
+    }
+
+    #[test]
+    fn rewrite() {
+        assert_eq!(rewrite_line("Q := A * /B"), "Q.R = A * /B");
+        assert_eq!(rewrite_line("O.trst = C :+: D"), "O.E = C $ D");
+        assert_eq!(rewrite_line("GLOBAL.RSTF = R"), "AR = R");
+    }
+}
@@ -10,9 +10,10 @@
 use std::{collections::HashMap, fs, iter::Peekable};
 
 use crate::{
-    chips::Chip,
-    errors::{at_line, Error, ErrorCode, LineNum},
-    gal::Pin,
+    chips::{self, Chip},
+    errors::{at_line, Error, ErrorCode, LineNum, Pos, NO_LINE},
+    gal::{Mode, Pin},
+    minimise,
 };
 
 ////////////////////////////////////////////////////////////////////////
@@ -24,14 +25,41 @@ pub struct Content {
     pub sig: Vec<u8>,
     pub pins: Vec<String>,
     pub eqns: Vec<Equation>,
+    // For the GALxV8s, the mode, if set by a MODE line rather than
+    // left to be worked out from the equations.
+    pub mode: Option<Mode>,
+    pub notes: Notes,
+}
+
+// Text from the input that doesn't affect the fuses, but is carried
+// through to the output files.
+#[derive(Clone, Debug, Default)]
+pub struct Notes {
+    // The lines of the DESCRIPTION section.
+    pub description: Vec<String>,
+    // Comments on the equations, by the line the equation starts on.
+    pub comments: HashMap<LineNum, Vec<String>>,
+    // Test vectors, with a JEDEC test condition character per pin.
+    pub vectors: Vec<String>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Equation {
     pub line_num: LineNum,
     pub lhs: LHS,
-    pub rhs: Vec<Pin>,
-    pub is_or: Vec<bool>,
+    pub rhs: Expr,
+}
+
+// An 'Expr' is the right-hand side of an equation, as written. It
+// gets converted into sum-of-products form when it's turned into a
+// Term in the Blueprint.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Pin(Pin),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Xor(Box<Expr>, Box<Expr>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -59,59 +87,197 @@ pub enum Suffix {
 // Bit of a hack, since we can't get the line number once we've fallen
 // off the end of the file. Use a special value that gets filled in
 // correctly at the top level.
-const EOF_LINE: LineNum = 0;
+pub(crate) const EOF_LINE: LineNum = 0;
 
-#[derive(Debug, Eq, PartialEq)]
-enum Token {
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token {
     Item((NamedPin, Suffix)),
     Equals,
     And,
     Or,
+    Xor,
+    Xnor,
+    Not,
+    LParen,
+    RParen,
+    EqEq,
+    NotEq,
+    Number(String),
+    Arrow,
+    Dash,
+    LBrace,
+    RBrace,
+    // A sub-expression that's already been parsed, used by other
+    // frontends (e.g. for CUPL field comparisons).
+    Expr(Expr),
 }
 
 #[derive(Debug, Eq, PartialEq)]
-struct NamedPin {
-    name: String,
-    neg: bool,
+pub(crate) struct NamedPin {
+    pub(crate) name: String,
+    pub(crate) neg: bool,
+    // For a bus, the first and last indices, as written. A single
+    // index is appended to the name instead.
+    pub(crate) bus: Option<(usize, usize)>,
+}
+
+// The names that can be used in equations: the pins, and any
+// DEFINEd expressions.
+pub(crate) struct Symbols {
+    pub(crate) chip: Chip,
+    pub(crate) pins: HashMap<String, Pin>,
+    pub(crate) defines: HashMap<String, Expr>,
+}
+
+impl Symbols {
+    pub(crate) fn new(chip: Chip, pins: HashMap<String, Pin>) -> Self {
+        Symbols {
+            chip,
+            pins,
+            defines: HashMap::new(),
+        }
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////
 // Input tokenisation
 //
 
-// Tokenise a full line.
-fn tokenise((line_num, s): (LineNum, &str)) -> Result<Vec<(LineNum, Token)>, Error> {
+// Tokenise a full line. Tokens are given their column range, for
+// error reporting.
+fn tokenise((line_num, s): (LineNum, &str)) -> Result<Vec<(Pos, Token)>, Error> {
+    let len = s.chars().count();
     let mut res = Vec::new();
     let mut chars = s.chars().peekable();
-    loop {
-        match chars.peek().cloned() {
-            Some(c) => match c {
-                '=' => {
-                    chars.next();
-                    res.push((line_num, Token::Equals));
-                }
-                '+' | '#' => {
-                    chars.next();
-                    res.push((line_num, Token::Or));
-                }
-                '*' | '&' => {
-                    chars.next();
-                    res.push((line_num, Token::And));
-                }
-                '/' => res.push(tokenise_pin(line_num, &mut chars)?),
-                c if c.is_ascii_alphabetic() => res.push(tokenise_pin(line_num, &mut chars)?),
-                c if c.is_whitespace() => {
+    while let Some(c) = chars.peek().cloned() {
+        let start = column(len, &chars);
+        let token = match c {
+            '=' if lookahead(&chars, 1) == Some('=') => {
+                chars.next();
+                chars.next();
+                Ok(Token::EqEq)
+            }
+            '!' if lookahead(&chars, 1) == Some('=') => {
+                chars.next();
+                chars.next();
+                Ok(Token::NotEq)
+            }
+            '=' if lookahead(&chars, 1) == Some('>') => {
+                chars.next();
+                chars.next();
+                Ok(Token::Arrow)
+            }
+            '=' => {
+                chars.next();
+                Ok(Token::Equals)
+            }
+            '-' => {
+                chars.next();
+                Ok(Token::Dash)
+            }
+            '{' => {
+                chars.next();
+                Ok(Token::LBrace)
+            }
+            '}' => {
+                chars.next();
+                Ok(Token::RBrace)
+            }
+            '+' | '#' => {
+                chars.next();
+                Ok(Token::Or)
+            }
+            '*' | '&' => {
+                chars.next();
+                Ok(Token::And)
+            }
+            '$' => {
+                chars.next();
+                Ok(Token::Xor)
+            }
+            '!' if lookahead(&chars, 1) == Some('$') => {
+                chars.next();
+                chars.next();
+                Ok(Token::Xnor)
+            }
+            '(' => {
+                chars.next();
+                Ok(Token::LParen)
+            }
+            ')' => {
+                chars.next();
+                Ok(Token::RParen)
+            }
+            // A '/' directly before a bracket negates the whole
+            // bracketed expression, otherwise it's part of a pin.
+            '/' if lookahead(&chars, 1) == Some('(') => {
+                chars.next();
+                Ok(Token::Not)
+            }
+            '/' => tokenise_pin(&mut chars),
+            c if c.is_ascii_alphabetic() => tokenise_pin(&mut chars),
+            c if c.is_ascii_digit() => {
+                // Numbers are only checked when they're used, as
+                // what's valid depends on context.
+                let mut num = String::new();
+                while let Some(c) = chars.peek().cloned().filter(char::is_ascii_alphanumeric) {
                     chars.next();
+                    num.push(c);
                 }
-                c => return err(line_num, ErrorCode::BadChar { c }),
-            },
-            None => return Ok(res),
+                Ok(Token::Number(num))
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            c => {
+                chars.next();
+                Err(ErrorCode::BadChar { c })
+            }
+        };
+        let pos = Pos {
+            line: line_num,
+            cols: Some((start, column(len, &chars))),
+        };
+        res.push((pos, at_line(pos, token)?));
+    }
+    Ok(res)
+}
+
+// 'XOR' and 'XNOR' may be written as words instead of '$' and '!$'.
+// They're only operators where an operator is expected, so they can
+// still be used as pin names. Returns whether it's XNOR.
+fn xor_word(token: &Token) -> Option<bool> {
+    match token {
+        Token::Item((pin, Suffix::None)) if !pin.neg && pin.bus.is_none() => {
+            match pin.name.as_str() {
+                "XOR" => Some(false),
+                "XNOR" => Some(true),
+                _ => None,
+            }
         }
+        _ => None,
     }
 }
 
+// The column of the next character, counting from 1.
+fn column<I>(len: usize, chars: &Peekable<I>) -> usize
+where
+    I: Iterator<Item = char> + Clone,
+{
+    len - chars.clone().count() + 1
+}
+
+// Peek 'n' characters beyond the next one.
+fn lookahead<I>(chars: &Peekable<I>, n: usize) -> Option<char>
+where
+    I: Iterator<Item = char> + Clone,
+{
+    chars.clone().nth(n)
+}
+
 // Tokenise a single pin name.
-fn tokenise_pin<I>(line_num: LineNum, chars: &mut Peekable<I>) -> Result<(LineNum, Token), Error>
+fn tokenise_pin<I>(chars: &mut Peekable<I>) -> Result<Token, ErrorCode>
 where
     I: Iterator<Item = char>,
 {
@@ -130,14 +296,14 @@ where
             chars.next();
             name.push(c);
         }
-        Some(c) => return err(line_num, ErrorCode::NoPinName { c }),
-        None => return err(line_num, ErrorCode::NoPinNameEOL),
+        Some(c) => return Err(ErrorCode::NoPinName { c }),
+        None => return Err(ErrorCode::NoPinNameEOL),
     }
 
-    // Body is alphanumeric
+    // Body is alphanumeric, or underscores
     loop {
         match chars.peek().cloned() {
-            Some(c) if c.is_ascii_alphanumeric() => {
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
                 chars.next();
                 name.push(c);
             }
@@ -145,7 +311,36 @@ where
         }
     }
 
-    let named_pin = NamedPin { name, neg };
+    // Look for a bus index or range.
+    let mut bus = None;
+    if chars.peek() == Some(&'[') {
+        chars.next();
+        let first = tokenise_index(chars)?;
+        let last = match chars.peek().cloned() {
+            Some(':') => {
+                chars.next();
+                Some(tokenise_index(chars)?)
+            }
+            Some('.') => {
+                chars.next();
+                if chars.next() != Some('.') {
+                    return Err(ErrorCode::BadBusRange);
+                }
+                Some(tokenise_index(chars)?)
+            }
+            _ => None,
+        };
+        if chars.next() != Some(']') {
+            return Err(ErrorCode::BadBusRange);
+        }
+
+        match last {
+            Some(last) => bus = Some(check_bus_range((first, last))?),
+            None => name.push_str(&first.to_string()),
+        }
+    }
+
+    let named_pin = NamedPin { name, neg, bus };
 
     // Look for extension
     let mut suffix = Suffix::None;
@@ -161,10 +356,23 @@ where
                 _ => break,
             }
         }
-        suffix = at_line(line_num, ext_to_suffix(&ext))?;
+        suffix = ext_to_suffix(&ext)?;
     }
 
-    Ok((line_num, Token::Item((named_pin, suffix))))
+    Ok(Token::Item((named_pin, suffix)))
+}
+
+// Tokenise a decimal index within a bus reference.
+fn tokenise_index<I>(chars: &mut Peekable<I>) -> Result<usize, ErrorCode>
+where
+    I: Iterator<Item = char>,
+{
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().cloned().filter(char::is_ascii_digit) {
+        chars.next();
+        digits.push(c);
+    }
+    digits.parse().map_err(|_| ErrorCode::BadBusRange)
 }
 
 fn ext_to_suffix(s: &str) -> Result<Suffix, ErrorCode> {
@@ -185,16 +393,30 @@ fn ext_to_suffix(s: &str) -> Result<Suffix, ErrorCode> {
 
 // Take an iterator that returns lines, convert it to an iterator that
 // converts lines and concatenates continuation lines.
-fn tokenised_lines<'a, I>(
-    lines: I,
-) -> impl Iterator<Item = Result<Vec<(LineNum, Token)>, Error>> + 'a
+fn tokenised_lines<'a, I>(lines: I) -> impl Iterator<Item = Result<Vec<(Pos, Token)>, Error>> + 'a
 where
     I: Iterator<Item = (LineNum, &'a str)> + 'a,
 {
-    type TokItem = Result<Vec<(LineNum, Token)>, Error>;
+    type TokItem = Result<Vec<(Pos, Token)>, Error>;
 
-    fn has_continuation(v: &[(LineNum, Token)]) -> bool {
-        matches!(v.last(), Some((_, Token::And)) | Some((_, Token::Or)))
+    fn has_continuation(v: &[(Pos, Token)]) -> bool {
+        // An XOR word is an operator if it follows an operand.
+        if let [.., (_, Token::Item(_) | Token::RParen), (_, last)] = v {
+            if xor_word(last).is_some() {
+                return true;
+            }
+        }
+        matches!(
+            v.last(),
+            Some((_, Token::And))
+                | Some((_, Token::Or))
+                | Some((_, Token::Xor))
+                | Some((_, Token::Xnor))
+                | Some((_, Token::Not))
+                | Some((_, Token::LParen))
+                | Some((_, Token::EqEq))
+                | Some((_, Token::NotEq))
+        )
     }
 
     fn is_continuation<I>(iter: &mut Peekable<I>) -> bool
@@ -202,7 +424,22 @@ where
         I: Iterator<Item = TokItem>,
     {
         if let Some(Ok(line)) = iter.peek() {
-            matches!(line.first(), Some((_, Token::And)) | Some((_, Token::Or)))
+            // A line starting 'XOR =' is an equation for a pin called XOR.
+            if let [(_, first), rest @ ..] = line.as_slice() {
+                if xor_word(first).is_some()
+                    && !matches!(rest.first(), None | Some((_, Token::Equals)))
+                {
+                    return true;
+                }
+            }
+            matches!(
+                line.first(),
+                Some((_, Token::And))
+                    | Some((_, Token::Or))
+                    | Some((_, Token::Xor))
+                    | Some((_, Token::Xnor))
+                    | Some((_, Token::RParen))
+            )
         } else {
             false
         }
@@ -255,9 +492,26 @@ fn remove_comment(s: &str) -> &str {
     }
 }
 
-fn next_or_fail<I, T>(iter: &mut I, err_code: ErrorCode) -> Result<(LineNum, T), Error>
+// Like 'remove_comment', for the signature line. A ';' inside the
+// quotes of a 'SIGNATURE = "..."' doesn't start a comment.
+fn remove_sig_comment(s: &str) -> &str {
+    if !s.trim_start().starts_with("SIGNATURE") {
+        return remove_comment(s);
+    }
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &s[..i],
+            _ => (),
+        }
+    }
+    s
+}
+
+fn next_or_fail<I, P, T>(iter: &mut I, err_code: ErrorCode) -> Result<(P, T), Error>
 where
-    I: Iterator<Item = (LineNum, T)>,
+    I: Iterator<Item = (P, T)>,
 {
     match iter.next() {
         Some(x) => Ok(x),
@@ -278,12 +532,50 @@ where
     at_line(line_num, Chip::from_name(name.trim()))
 }
 
+// The signature line is normally taken as text, but can also be
+// given as 'SIGNATURE = value', to allow raw bytes. Only the first 8
+// bytes fit in the GAL, but we keep them all so that truncation can be
+// warned about.
 fn parse_signature<'a, I>(line_iter: &mut I) -> Result<Vec<u8>, Error>
 where
     I: Iterator<Item = (LineNum, &'a str)>,
 {
-    let (_, sig) = next_or_fail(line_iter, ErrorCode::BadSigEOF)?;
-    Ok(sig.bytes().take(8).collect::<Vec<u8>>())
+    let (line_num, sig) = next_or_fail(line_iter, ErrorCode::BadSigEOF)?;
+    let sig = sig.trim_start();
+    match sig
+        .strip_prefix("SIGNATURE")
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+    {
+        Some(value) => at_line(line_num, parse_signature_value(value.trim())),
+        None => Ok(sig.bytes().collect()),
+    }
+}
+
+// Parse an explicit signature: '0x' followed by pairs of hex digits
+// for raw bytes, text in double quotes, or just plain text.
+pub fn parse_signature_value(value: &str) -> Result<Vec<u8>, ErrorCode> {
+    let bad_sig = || ErrorCode::BadSignature {
+        sig: value.to_string(),
+    };
+    if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        if hex.is_empty() || hex.len() % 2 != 0 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(bad_sig());
+        }
+        Ok((0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect())
+    } else if let Some(quoted) = value.strip_prefix('"') {
+        quoted
+            .strip_suffix('"')
+            .map(|text| text.bytes().collect())
+            .ok_or_else(bad_sig)
+    } else {
+        Ok(value.bytes().collect())
+    }
 }
 
 // Parse one line of pins
@@ -299,16 +591,21 @@ where
     let mut pins = Vec::new();
     let line @ (line_num, _) = next_or_fail(line_iter, ErrorCode::BadPinEOF)?;
     let tokens = tokenise(line)?;
-    let len = tokens.len();
     for token in tokens.into_iter() {
         match token {
-            (_, Token::Item((name, suffix))) if suffix == Suffix::None => {
-                pins.push((name.name, name.neg))
-            }
+            (_, Token::Item((name, Suffix::None))) => match name.bus {
+                Some(range) => {
+                    for (bit_name, _) in bus_bits(&name.name, range) {
+                        pins.push((bit_name, name.neg));
+                    }
+                }
+                None => pins.push((name.name, name.neg)),
+            },
             (line_num, Token::Item(_)) => return err(line_num, ErrorCode::BadPinSuffix),
             (line_num, _) => return err(line_num, ErrorCode::BadToken { expected: "pin" }),
         }
     }
+    let len = pins.len();
 
     // We test this afterwards in case there was a bad token
     // causing us to miscount. In that case, the earlier error
@@ -329,25 +626,109 @@ where
     Ok(pins)
 }
 
-fn lookup_pin(
-    chip: Chip,
-    pin_map: &HashMap<String, Pin>,
-    pin_name: &NamedPin,
-) -> Result<Pin, ErrorCode> {
-    let pin = pin_map
-        .get(pin_name.name.as_str())
-        .ok_or_else(|| match pin_name.name.as_str() {
-            "NC" => ErrorCode::BadNC,
-            "AR" if chip == Chip::GAL22V10 => ErrorCode::BadSpecial {
-                term: pin_name.name.parse().unwrap(),
-            },
-            "SP" if chip == Chip::GAL22V10 => ErrorCode::BadSpecial {
-                term: pin_name.name.parse().unwrap(),
-            },
-            _ => ErrorCode::UnknownPin {
-                name: pin_name.name.clone(),
+// Is this a 'PIN n = name' line?
+fn is_pin_decl(line: (LineNum, &str)) -> bool {
+    match tokenise(line) {
+        Ok(tokens) => {
+            is_keyword(&tokens, "PIN")
+                && matches!(
+                    tokens.get(1..3),
+                    Some([(_, Token::Number(_)), (_, Token::Equals)])
+                )
+        }
+        Err(_) => false,
+    }
+}
+
+// Parse a 'PIN n = name' line, where the name may be negated.
+fn parse_pin_decl(line: (LineNum, &str)) -> Result<PinDecl, Error> {
+    let line_num = line.0;
+    let mut tokens = tokenise(line)?.into_iter().skip(1);
+
+    let pin_num = match tokens.next() {
+        Some((line_num, Token::Number(num))) => num
+            .parse()
+            .or_else(|_| err(line_num, ErrorCode::BadNumber { num }))?,
+        _ => return err(line_num, ErrorCode::BadToken { expected: "number" }),
+    };
+    tokens.next();
+
+    let name = match tokens.next() {
+        Some((_, Token::Item((named_pin, Suffix::None)))) if named_pin.bus.is_none() => {
+            (named_pin.name, named_pin.neg)
+        }
+        Some((line_num, Token::Item((_, Suffix::None)))) => {
+            return err(line_num, ErrorCode::BadToken { expected: "pin" })
+        }
+        Some((line_num, Token::Item(_))) => return err(line_num, ErrorCode::BadPinSuffix),
+        Some((line_num, _)) => return err(line_num, ErrorCode::BadToken { expected: "pin" }),
+        None => return err(line_num, ErrorCode::BadEOL),
+    };
+    if let Some((line_num, _)) = tokens.next() {
+        return err(
+            line_num,
+            ErrorCode::BadToken {
+                expected: "end of line",
             },
-        })?;
+        );
+    }
+
+    Ok((line_num, vec![pin_num], vec![name]))
+}
+
+// No bus can be wider than the largest chip has pins, and expanding an
+// unbounded range could exhaust memory, so reject anything wider.
+pub(crate) fn check_bus_range((first, last): (usize, usize)) -> Result<(usize, usize), ErrorCode> {
+    if usize::max(first, last) - usize::min(first, last) >= chips::MAX_PINS {
+        return Err(ErrorCode::BadBusRange);
+    }
+    Ok((first, last))
+}
+
+// Expand a bus into the names of its individual pins, in the order
+// written, along with the significance of each pin's bit.
+pub(crate) fn bus_bits(name: &str, (first, last): (usize, usize)) -> Vec<(String, usize)> {
+    let indices: Vec<usize> = if first >= last {
+        (last..=first).rev().collect()
+    } else {
+        (first..=last).collect()
+    };
+    let lsb = usize::min(first, last);
+    indices
+        .into_iter()
+        .map(|i| (format!("{}{}", name, i), i - lsb))
+        .collect()
+}
+
+fn parse_number(s: &str) -> Result<u64, ErrorCode> {
+    let res = if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2)
+    } else {
+        s.parse()
+    };
+    res.map_err(|_| ErrorCode::BadNumber { num: s.to_string() })
+}
+
+fn lookup_pin(symbols: &Symbols, pin_name: &NamedPin) -> Result<Pin, ErrorCode> {
+    let chip = symbols.chip;
+    let pin =
+        symbols
+            .pins
+            .get(pin_name.name.as_str())
+            .ok_or_else(|| match pin_name.name.as_str() {
+                "NC" => ErrorCode::BadNC,
+                "AR" if chip.family() == Chip::GAL22V10 => ErrorCode::BadSpecial {
+                    term: pin_name.name.parse().unwrap(),
+                },
+                "SP" if chip.family() == Chip::GAL22V10 => ErrorCode::BadSpecial {
+                    term: pin_name.name.parse().unwrap(),
+                },
+                _ => ErrorCode::UnknownPin {
+                    name: pin_name.name.clone(),
+                },
+            })?;
 
     Ok(Pin {
         pin: pin.pin,
@@ -355,31 +736,28 @@ fn lookup_pin(
     })
 }
 
-// Read a pin on the RHS (where suffices are not allowed), and convert to pin number.
-fn parse_pin<I>(chip: Chip, pin_map: &HashMap<String, Pin>, iter: &mut I) -> Result<Pin, Error>
-where
-    I: Iterator<Item = (LineNum, Token)>,
-{
-    let (line_num, token) = next_or_fail(iter, ErrorCode::BadEOL)?;
-    if let Token::Item((named_pin, suffix)) = token {
-        if suffix != Suffix::None {
-            err(line_num, ErrorCode::BadPinSuffix)
-        } else {
-            at_line(line_num, lookup_pin(chip, pin_map, &named_pin))
-        }
-    } else {
-        err(line_num, ErrorCode::BadToken { expected: "pin" })
-    }
-}
-
 // Parse and check the LHS (where suffices are allowed, but there are other constraints)
-fn parse_lhs<I>(chip: Chip, pin_map: &HashMap<String, Pin>, iter: &mut I) -> Result<LHS, Error>
+pub(crate) fn parse_lhs<I>(symbols: &Symbols, iter: &mut I) -> Result<LHS, Error>
 where
-    I: Iterator<Item = (LineNum, Token)>,
+    I: Iterator<Item = (Pos, Token)>,
 {
     Ok(match iter.next() {
+        Some((line_num, Token::Item((named_pin, _)))) if named_pin.bus.is_some() => {
+            return err(line_num, ErrorCode::BadToken { expected: "pin" })
+        }
         Some((line_num, Token::Item((named_pin, suffix)))) => {
-            if chip == Chip::GAL22V10 && (named_pin.name == "AR" || named_pin.name == "SP") {
+            if symbols.defines.contains_key(&named_pin.name) {
+                return err(
+                    line_num,
+                    ErrorCode::AssignedDefine {
+                        name: named_pin.name,
+                    },
+                );
+            }
+
+            if symbols.chip.family() == Chip::GAL22V10
+                && (named_pin.name == "AR" || named_pin.name == "SP")
+            {
                 if suffix != Suffix::None {
                     return err(
                         line_num,
@@ -403,7 +781,7 @@ where
                     LHS::Sp
                 }
             } else {
-                let pin = at_line(line_num, lookup_pin(chip, pin_map, &named_pin))?;
+                let pin = at_line(line_num, lookup_pin(symbols, &named_pin))?;
                 LHS::Pin((pin, suffix))
             }
         }
@@ -411,179 +789,1667 @@ where
     })
 }
 
-fn parse_equation<I>(
-    chip: Chip,
-    pin_map: &HashMap<String, Pin>,
-    tokens: &mut I,
-) -> Result<Equation, Error>
+// Parse a 'DEFINE name = expr' line, adding it to the symbol table.
+// The 'DEFINE' keyword has already been consumed.
+fn parse_define<I>(symbols: &mut Symbols, tokens: &mut Peekable<I>) -> Result<(), Error>
 where
-    I: Iterator<Item = (LineNum, Token)>,
+    I: Iterator<Item = (Pos, Token)>,
 {
-    let lhs = parse_lhs(chip, pin_map, tokens)?;
+    let (line_num, token) = next_or_fail(tokens, ErrorCode::BadEOL)?;
+    let name = match token {
+        Token::Item((named_pin, Suffix::None)) if !named_pin.neg && named_pin.bus.is_none() => {
+            named_pin.name
+        }
+        Token::Item(_) => return err(line_num, ErrorCode::BadPinSuffix),
+        _ => return err(line_num, ErrorCode::BadToken { expected: "name" }),
+    };
+    if symbols.pins.contains_key(&name) || symbols.defines.contains_key(&name) {
+        return err(line_num, ErrorCode::RepeatedPinName { name });
+    }
 
     let (line_num, eq_token) = next_or_fail(tokens, ErrorCode::BadEquationEOF)?;
     if eq_token != Token::Equals {
         return err(line_num, ErrorCode::NoEquals);
     }
 
-    let mut rhs = vec![parse_pin(chip, pin_map, tokens)?];
-    let mut is_or = vec![false];
+    let expr = parse_expr(symbols, tokens)?;
+    if let Some((token_line_num, _)) = tokens.next() {
+        return err(
+            token_line_num,
+            ErrorCode::BadToken {
+                expected: "+, #, *, &, $ or !$",
+            },
+        );
+    }
+
+    symbols.defines.insert(name, expr);
+    Ok(())
+}
+
+// Does the line start with the given keyword?
+fn is_keyword(tokens: &[(Pos, Token)], keyword: &str) -> bool {
+    matches!(
+        tokens.first(),
+        Some((_, Token::Item((NamedPin { name, neg: false, bus: None }, Suffix::None))))
+            if name == keyword
+    )
+}
+
+// Does the line start with the given directive? A pin may have the
+// same name, so check the line isn't an equation assigning to it.
+fn is_directive(tokens: &[(Pos, Token)], keyword: &str) -> bool {
+    is_keyword(tokens, keyword) && !matches!(tokens.get(1), Some((_, Token::Equals)))
+}
+
+// Parse a 'MODE SIMPLE', 'MODE COMPLEX' or 'MODE REGISTERED' line,
+// forcing the mode of a GALxV8 (e.g. to match the PAL it replaces).
+// The 'MODE' keyword has already been consumed.
+fn parse_mode<I>(chip: Chip, line_num: LineNum, tokens: &mut I) -> Result<Mode, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    if chip.family() != Chip::GAL16V8 && chip.family() != Chip::GAL20V8 {
+        return err(line_num, ErrorCode::ModeNotSupported);
+    }
+    let expected = ErrorCode::BadToken {
+        expected: "SIMPLE, COMPLEX or REGISTERED",
+    };
+    let (pos, token) = next_or_fail(tokens, expected.clone())?;
+    let mode = match token {
+        Token::Item((NamedPin { name, .. }, Suffix::None)) => {
+            Mode::from_name(&name.to_ascii_lowercase())
+        }
+        _ => None,
+    };
+    let mode = at_line(pos, mode.ok_or(expected))?;
+    if let Some((pos, _)) = tokens.next() {
+        return err(
+            pos,
+            ErrorCode::BadToken {
+                expected: "end of line",
+            },
+        );
+    }
+    Ok(mode)
+}
+
+// Parse a truth table, of the form:
+//
+//   TABLE I1 I2 I3 => O1 O2 {
+//       0 1 - => 1 0
+//       ...
+//   }
+//
+// and turn each output column into an equation. '-' means don't care,
+// and outputs are false for any inputs not listed. The 'TABLE'
+// keyword has already been consumed.
+fn parse_table<I, L>(
+    symbols: &Symbols,
+    line_num: LineNum,
+    header: &mut I,
+    lines: &mut L,
+) -> Result<Vec<Equation>, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+    L: Iterator<Item = Result<Vec<(Pos, Token)>, Error>>,
+{
+    // Read the inputs and outputs from the header.
+    let mut inputs = Vec::new();
+    loop {
+        match header.next() {
+            Some((_, Token::Arrow)) => break,
+            Some((line_num, Token::Item((named_pin, Suffix::None)))) => {
+                for named_pin in expand_bus(named_pin) {
+                    inputs.push(at_line(line_num, lookup_pin(symbols, &named_pin))?);
+                }
+            }
+            Some((line_num, Token::Item(_))) => return err(line_num, ErrorCode::BadPinSuffix),
+            Some((line_num, _)) => return err(line_num, ErrorCode::BadToken { expected: "pin" }),
+            None => return err(line_num, ErrorCode::BadToken { expected: "=>" }),
+        }
+    }
+
+    let mut outputs = Vec::new();
+    loop {
+        match header.next() {
+            Some((_, Token::LBrace)) => break,
+            Some((line_num, Token::Item((named_pin, suffix)))) => {
+                for named_pin in expand_bus(named_pin) {
+                    let token = Token::Item((named_pin, suffix));
+                    outputs.push(parse_lhs(symbols, &mut std::iter::once((line_num, token)))?);
+                }
+            }
+            Some((line_num, _)) => return err(line_num, ErrorCode::BadToken { expected: "pin" }),
+            None => return err(line_num, ErrorCode::BadToken { expected: "{" }),
+        }
+    }
+    if let Some((line_num, _)) = header.next() {
+        return err(
+            line_num,
+            ErrorCode::BadToken {
+                expected: "end of line",
+            },
+        );
+    }
 
+    // Read the rows, collecting the products for each output, the
+    // don't cares, and the explicit zeros.
+    let mut on_sets = vec![Vec::new(); outputs.len()];
+    let mut dc_sets = vec![Vec::new(); outputs.len()];
+    let mut off_sets = vec![Vec::new(); outputs.len()];
     loop {
-        match tokens.next() {
-            Some((_, Token::And)) => {
-                is_or.push(false);
-                rhs.push(parse_pin(chip, pin_map, tokens)?);
+        let row = match lines.next() {
+            Some(row) => row?,
+            None => return err(line_num, ErrorCode::UnterminatedBlock { block: "table" }),
+        };
+        if matches!(row.as_slice(), [(_, Token::RBrace)]) {
+            break;
+        }
+
+        let row_line_num = row[0].0.line;
+        let mut row = row.into_iter();
+        let in_bits = parse_table_bits(row_line_num, &mut row, inputs.len(), "input")?;
+        let out_bits = parse_table_bits(row_line_num, &mut row, outputs.len(), "output")?;
+
+        let product = inputs
+            .iter()
+            .zip(in_bits.iter())
+            .filter_map(|(pin, bit)| {
+                bit.map(|bit| Pin {
+                    pin: pin.pin,
+                    neg: pin.neg == bit,
+                })
+            })
+            .collect::<Vec<Pin>>();
+
+        for (i, bit) in out_bits.iter().enumerate() {
+            match bit {
+                Some(true) => on_sets[i].push(product.clone()),
+                None => dc_sets[i].push(product.clone()),
+                Some(false) => off_sets[i].push(product.clone()),
             }
-            Some((_, Token::Or)) => {
-                is_or.push(true);
-                rhs.push(parse_pin(chip, pin_map, tokens)?);
+        }
+    }
+
+    Ok(outputs
+        .into_iter()
+        .enumerate()
+        .map(|(i, lhs)| {
+            // A don't care row mustn't override a row that explicitly
+            // gives 0. If that's too hard to work out, we make do
+            // without the don't cares.
+            let dc_set = minimise::subtract(&dc_sets[i], &off_sets[i]).unwrap_or_default();
+            let products = minimise::minimise_with_dont_cares(&on_sets[i], &dc_set);
+            let rhs = Expr::Or(
+                products
+                    .into_iter()
+                    .map(|product| Expr::And(product.into_iter().map(Expr::Pin).collect()))
+                    .collect(),
+            );
+            Equation { line_num, lhs, rhs }
+        })
+        .collect())
+}
+
+// Read one side of a truth table row, up to the '=>' or end of line.
+// Bits are returned as Some(value), or None for don't care.
+fn parse_table_bits<I>(
+    line_num: LineNum,
+    row: &mut I,
+    expected: usize,
+    side: &'static str,
+) -> Result<Vec<Option<bool>>, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let mut bits = Vec::new();
+    loop {
+        match row.next() {
+            Some((_, Token::Arrow)) | None => break,
+            Some((_, Token::Dash)) => bits.push(None),
+            Some((line_num, Token::Number(num))) => {
+                for c in num.chars() {
+                    match c {
+                        '0' => bits.push(Some(false)),
+                        '1' => bits.push(Some(true)),
+                        _ => return err(line_num, ErrorCode::BadTableBit { c }),
+                    }
+                }
             }
-            Some((token_line_num, _)) => {
+            Some((line_num, _)) => {
                 return err(
-                    token_line_num,
+                    line_num,
                     ErrorCode::BadToken {
-                        expected: "+, #, * or &",
+                        expected: "0, 1 or -",
                     },
                 )
             }
-            None => break,
         }
     }
 
-    Ok(Equation {
-        line_num,
-        lhs,
-        rhs,
-        is_or,
-    })
+    if bits.len() != expected {
+        return err(
+            line_num,
+            ErrorCode::BadTableRow {
+                side,
+                expected,
+                found: bits.len(),
+            },
+        );
+    }
+    Ok(bits)
 }
 
-// Add a row's worth of pins to the pin map.
-fn extend_pin_map(
-    pin_map: &mut HashMap<String, Pin>,
-    chip: Chip,
-    row_num: usize,
-    pins: &[(String, bool)],
-) -> Result<(), ErrorCode> {
-    let num_pins = chip.num_pins();
-    let first_pin = 1 + row_num * num_pins / 2;
-    for ((name, neg), pin_num) in pins.iter().cloned().zip(first_pin..) {
-        if pin_num == num_pins && (name.as_str(), neg) != ("VCC", false) {
-            return Err(ErrorCode::InvalidPowerPinName {
-                pin: pin_num,
-                name: "VCC",
-            });
-        }
-        if pin_num == num_pins / 2 && (name.as_str(), neg) != ("GND", false) {
-            return Err(ErrorCode::InvalidPowerPinName {
-                pin: pin_num,
-                name: "GND",
-            });
-        }
-        if name == "VCC" && pin_num != num_pins {
-            return Err(ErrorCode::InvalidPowerPinLocation {
-                pin: pin_num,
-                name: "VCC",
-                expected_pin: num_pins,
-            });
-        }
-        if name == "GND" && pin_num != num_pins / 2 {
-            return Err(ErrorCode::InvalidPowerPinLocation {
-                pin: pin_num,
-                name: "GND",
-                expected_pin: num_pins / 2,
-            });
-        }
-        if name != "NC" {
-            if pin_map.contains_key(&name) {
-                return Err(ErrorCode::RepeatedPinName { name });
-            }
-
-            if chip == Chip::GAL22V10 {
-                // parse returns Ok if name is "AR" or "SP"
-                if let Ok(term) = name.parse() {
-                    return Err(ErrorCode::ReservedPinName { term });
+// Parse a block of test vectors, of the form:
+//
+//   VECTORS CLK A B => Y Z {
+//       C 0 1 => L H
+//       C 1 - => 0 Z
+//   }
+//
+// and turn each row into a JEDEC test vector. Inputs are driven with
+// 0 or 1, or clocked with C (low-high-low) or K (high-low-high).
+// Outputs are checked for L (or 0), H (or 1) or Z. X or '-' means
+// don't care, as it does for any pin not listed, apart from the power
+// pins. The 'VECTORS' keyword has already been consumed.
+fn parse_vectors<I, L>(
+    symbols: &Symbols,
+    line_num: LineNum,
+    header: &mut I,
+    lines: &mut L,
+) -> Result<Vec<String>, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+    L: Iterator<Item = Result<Vec<(Pos, Token)>, Error>>,
+{
+    // Read the inputs and outputs from the header.
+    let mut pins = (Vec::new(), Vec::new());
+    let mut side = &mut pins.0;
+    let mut seen: &[usize] = &[];
+    loop {
+        match header.next() {
+            Some((_, Token::LBrace)) => break,
+            Some((_, Token::Arrow)) => {
+                seen = &pins.0;
+                side = &mut pins.1;
+            }
+            Some((line_num, Token::Item((named_pin, Suffix::None)))) => {
+                for named_pin in expand_bus(named_pin) {
+                    let pin = at_line(line_num, lookup_pin(symbols, &named_pin))?.pin;
+                    // A pin can only be driven or checked once.
+                    if side.contains(&pin) || seen.contains(&pin) {
+                        return err(
+                            line_num,
+                            ErrorCode::RepeatedVectorPin {
+                                name: named_pin.name,
+                            },
+                        );
+                    }
+                    side.push(pin);
                 }
             }
+            Some((line_num, Token::Item(_))) => return err(line_num, ErrorCode::BadPinSuffix),
+            Some((line_num, _)) => return err(line_num, ErrorCode::BadToken { expected: "pin" }),
+            None => return err(line_num, ErrorCode::BadToken { expected: "{" }),
+        }
+    }
+    if let Some((line_num, _)) = header.next() {
+        return err(
+            line_num,
+            ErrorCode::BadToken {
+                expected: "end of line",
+            },
+        );
+    }
+    let (inputs, outputs) = pins;
+
+    let num_pins = symbols.chip.num_pins();
+    let mut vectors = Vec::new();
+    loop {
+        let row = match lines.next() {
+            Some(row) => row?,
+            None => return err(line_num, ErrorCode::UnterminatedBlock { block: "vectors" }),
+        };
+        if matches!(row.as_slice(), [(_, Token::RBrace)]) {
+            break;
+        }
 
-            pin_map.insert(name, Pin { pin: pin_num, neg });
+        let row_line_num = row[0].0.line;
+        let mut row = row.into_iter();
+        let in_bits = parse_vector_bits(row_line_num, &mut row, inputs.len(), "input")?;
+        let out_bits = parse_vector_bits(row_line_num, &mut row, outputs.len(), "output")?;
+
+        let mut vector = vec!['X'; num_pins];
+        vector[num_pins / 2 - 1] = 'N';
+        vector[num_pins - 1] = 'N';
+        for (pin, bit) in inputs
+            .iter()
+            .zip(in_bits)
+            .chain(outputs.iter().zip(out_bits))
+        {
+            vector[pin - 1] = bit;
         }
+        vectors.push(vector.into_iter().collect());
     }
 
-    Ok(())
+    Ok(vectors)
 }
 
-fn parse_core<'a, I>(line_iter: I) -> Result<Content, Error>
+// Read one side of a test vector row, up to the '=>' or end of line,
+// as JEDEC test condition characters. As the row is tokenised like an
+// equation, letters come through as pin names.
+fn parse_vector_bits<I>(
+    line_num: LineNum,
+    row: &mut I,
+    expected: usize,
+    side: &'static str,
+) -> Result<Vec<char>, Error>
 where
-    I: Iterator<Item = (LineNum, &'a str)>,
+    I: Iterator<Item = (Pos, Token)>,
 {
-    // Ignore comments (and start/end-of-line whitespace) on all lines.
-    let mut line_iter = line_iter.map(|(i, x)| (i, str::trim(remove_comment(x))));
-
-    // Chip type and signature must be on first two lines.
-    let chip = parse_chip(&mut line_iter)?;
-    let signature = parse_signature(&mut line_iter)?;
-
-    // We now ignore blank lines. Unlike galasm, we don't *require* a
-    // DESCRIPTION line, but if we encounter one we stop there.
-    let mut line_iter = line_iter
-        .filter(|(_, x)| !x.is_empty())
-        .take_while(|(_, x)| *x != "DESCRIPTION");
-
-    let mut pin_map = HashMap::new();
-    let mut pins = parse_pins(&mut pin_map, chip, 0, &mut line_iter)?;
-    let mut pins2 = parse_pins(&mut pin_map, chip, 1, &mut line_iter)?;
-    pins.append(&mut pins2);
+    let mut bits = Vec::new();
+    loop {
+        let (pos, chars) = match row.next() {
+            Some((_, Token::Arrow)) | None => break,
+            Some((pos, Token::Dash)) => (pos, "X".to_string()),
+            Some((pos, Token::Number(num))) => (pos, num),
+            Some((
+                pos,
+                Token::Item((
+                    NamedPin {
+                        name,
+                        neg: false,
+                        bus: None,
+                    },
+                    Suffix::None,
+                )),
+            )) => (pos, name),
+            Some((pos, _)) => {
+                return err(
+                    pos,
+                    ErrorCode::BadToken {
+                        expected: "test vector values",
+                    },
+                )
+            }
+        };
+        for c in chars.chars() {
+            let bit = match (side, c.to_ascii_uppercase()) {
+                (_, c @ 'X') | ("input", c @ ('0' | '1' | 'C' | 'K')) => c,
+                ("output", '0' | 'L') => 'L',
+                ("output", '1' | 'H') => 'H',
+                ("output", 'Z') => 'Z',
+                _ => return err(pos, ErrorCode::BadVectorBit { side, c }),
+            };
+            bits.push(bit);
+        }
+    }
+
+    if bits.len() != expected {
+        return err(
+            line_num,
+            ErrorCode::BadTableRow {
+                side,
+                expected,
+                found: bits.len(),
+            },
+        );
+    }
+    Ok(bits)
+}
+
+// A state machine transition: a condition (None meaning always), and
+// the next state.
+type Transition = (Option<Expr>, u64);
+
+// Parse a state machine, of the form:
+//
+//   SEQUENCE Q1 Q0 {
+//       STATE 0
+//           IF REQ NEXT 1
+//       STATE 1
+//           IF /REQ * DONE NEXT 2
+//           IF /REQ NEXT 0
+//       STATE 2
+//           NEXT 0
+//   }
+//
+// and turn it into registered equations for the state bits, which
+// are listed most significant first. Within a state, the first
+// transition whose condition holds is taken, and if none do, the
+// machine stays where it is. States not listed go to state 0. The
+// 'SEQUENCE' keyword has already been consumed.
+fn parse_sequence<I, L>(
+    symbols: &Symbols,
+    line_num: LineNum,
+    header: &mut I,
+    lines: &mut L,
+) -> Result<Vec<Equation>, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+    L: Iterator<Item = Result<Vec<(Pos, Token)>, Error>>,
+{
+    let mut bits = Vec::new();
+    loop {
+        match header.next() {
+            Some((_, Token::LBrace)) => break,
+            Some((line_num, Token::Item((named_pin, Suffix::None)))) => {
+                for named_pin in expand_bus(named_pin) {
+                    bits.push(at_line(line_num, lookup_pin(symbols, &named_pin))?);
+                }
+            }
+            Some((line_num, Token::Item(_))) => return err(line_num, ErrorCode::BadPinSuffix),
+            Some((line_num, _)) => return err(line_num, ErrorCode::BadToken { expected: "pin" }),
+            None => return err(line_num, ErrorCode::BadToken { expected: "{" }),
+        }
+    }
+    if let Some((line_num, _)) = header.next() {
+        return err(
+            line_num,
+            ErrorCode::BadToken {
+                expected: "end of line",
+            },
+        );
+    }
+
+    // For each state, the list of transitions.
+    let mut states: Vec<(u64, Vec<Transition>)> = Vec::new();
+    loop {
+        let tokens = match lines.next() {
+            Some(tokens) => tokens?,
+            None => return err(line_num, ErrorCode::UnterminatedBlock { block: "sequence" }),
+        };
+        if matches!(tokens.as_slice(), [(_, Token::RBrace)]) {
+            break;
+        }
+
+        let keyword_line = tokens[0].0;
+        let is_state = is_keyword(&tokens, "STATE");
+        let is_if = is_keyword(&tokens, "IF");
+        let is_next = is_keyword(&tokens, "NEXT");
+        let mut tokens = tokens.into_iter().skip(1).peekable();
+
+        if is_state {
+            let state = parse_state_code(keyword_line.line, &bits, &mut tokens)?;
+            if let Some((line_num, _)) = tokens.next() {
+                return err(
+                    line_num,
+                    ErrorCode::BadToken {
+                        expected: "end of line",
+                    },
+                );
+            }
+            if states.iter().any(|(s, _)| *s == state) {
+                return err(keyword_line, ErrorCode::RepeatedState { state });
+            }
+            states.push((state, Vec::new()));
+            continue;
+        }
+
+        let transitions = match states.last_mut() {
+            Some((_, transitions)) if is_if || is_next => transitions,
+            _ => return err(keyword_line, ErrorCode::BadToken { expected: "STATE" }),
+        };
+
+        let cond = if is_if {
+            let expr = parse_expr(symbols, &mut tokens)?;
+            match tokens.next() {
+                Some((
+                    _,
+                    Token::Item((
+                        NamedPin {
+                            name,
+                            neg: false,
+                            bus: None,
+                        },
+                        Suffix::None,
+                    )),
+                )) if name == "NEXT" => {}
+                Some((line_num, _)) => {
+                    return err(line_num, ErrorCode::BadToken { expected: "NEXT" })
+                }
+                None => return err(keyword_line, ErrorCode::BadToken { expected: "NEXT" }),
+            }
+            Some(expr)
+        } else {
+            None
+        };
+        let next = parse_state_code(keyword_line.line, &bits, &mut tokens)?;
+        if let Some((line_num, _)) = tokens.next() {
+            return err(
+                line_num,
+                ErrorCode::BadToken {
+                    expected: "end of line",
+                },
+            );
+        }
+        transitions.push((cond, next));
+    }
+
+    // Build up the products that set each state bit.
+    let mut bit_terms = vec![Vec::new(); bits.len()];
+    for (state, transitions) in states.iter() {
+        let present = Expr::And(
+            state_bit_values(*state, bits.len())
+                .zip(bits.iter())
+                .map(|(value, pin)| {
+                    Expr::Pin(Pin {
+                        pin: pin.pin,
+                        neg: pin.neg == value,
+                    })
+                })
+                .collect(),
+        );
+
+        // Earlier transitions take priority over later ones.
+        let mut taken = Vec::new();
+        let mut always = false;
+        for (cond, next) in transitions.iter() {
+            let mut factors = vec![present.clone()];
+            factors.extend(taken.iter().map(|c: &Expr| Expr::Not(Box::new(c.clone()))));
+            match cond {
+                Some(cond) => {
+                    factors.push(cond.clone());
+                    taken.push(cond.clone());
+                }
+                None => always = true,
+            }
+            add_state_terms(&mut bit_terms, *next, Expr::And(factors));
+            if always {
+                break;
+            }
+        }
+
+        // Otherwise, hold the current state.
+        if !always {
+            let mut factors = vec![present.clone()];
+            factors.extend(taken.iter().map(|c| Expr::Not(Box::new(c.clone()))));
+            add_state_terms(&mut bit_terms, *state, Expr::And(factors));
+        }
+    }
+
+    Ok(bits
+        .into_iter()
+        .zip(bit_terms)
+        .map(|(pin, terms)| Equation {
+            line_num,
+            lhs: LHS::Pin((pin, Suffix::R)),
+            rhs: Expr::Or(terms),
+        })
+        .collect())
+}
+
+// Parse a state number, checking it fits in the state bits.
+fn parse_state_code<I>(line_num: LineNum, bits: &[Pin], tokens: &mut I) -> Result<u64, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let (pos, state) = match tokens.next() {
+        Some((pos, Token::Number(num))) => (pos, at_line(pos, parse_number(&num))?),
+        Some((pos, _)) => return err(pos, ErrorCode::BadToken { expected: "number" }),
+        None => return err(line_num, ErrorCode::BadToken { expected: "number" }),
+    };
+    if bits.len() < 64 && state >> bits.len() != 0 {
+        return err(
+            pos,
+            ErrorCode::BusValueTooWide {
+                value: state,
+                width: bits.len(),
+            },
+        );
+    }
+    Ok(state)
+}
+
+// The value of each state bit, most significant first.
+fn state_bit_values(state: u64, width: usize) -> impl Iterator<Item = bool> {
+    (0..width).rev().map(move |i| (state >> i) & 1 == 1)
+}
+
+// Add the condition to the terms for the bits that are set in the
+// next state.
+fn add_state_terms(bit_terms: &mut [Vec<Expr>], next: u64, cond: Expr) {
+    for (value, terms) in state_bit_values(next, bit_terms.len()).zip(bit_terms.iter_mut()) {
+        if value {
+            terms.push(cond.clone());
+        }
+    }
+}
+
+// Turn a named bus into its individual pins. Plain pins are passed
+// through.
+fn expand_bus(named_pin: NamedPin) -> Vec<NamedPin> {
+    match named_pin.bus {
+        Some(range) => bus_bits(&named_pin.name, range)
+            .into_iter()
+            .map(|(name, _)| NamedPin {
+                name,
+                neg: named_pin.neg,
+                bus: None,
+            })
+            .collect(),
+        None => vec![named_pin],
+    }
+}
+
+fn parse_equation<I>(symbols: &Symbols, tokens: &mut Peekable<I>) -> Result<Equation, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let lhs = parse_lhs(symbols, tokens)?;
+
+    let (line_num, eq_token) = next_or_fail(tokens, ErrorCode::BadEquationEOF)?;
+    if eq_token != Token::Equals {
+        return err(line_num, ErrorCode::NoEquals);
+    }
+
+    let rhs = parse_expr(symbols, tokens)?;
+
+    // Anything left over means we didn't find an operator where we
+    // expected one.
+    if let Some((token_line_num, _)) = tokens.next() {
+        return err(
+            token_line_num,
+            ErrorCode::BadToken {
+                expected: "+, #, *, &, $ or !$",
+            },
+        );
+    }
+
+    Ok(Equation {
+        line_num: line_num.line,
+        lhs,
+        rhs,
+    })
+}
+
+// Parse an expression, where AND binds more tightly than OR, which
+// binds more tightly than XOR and XNOR:
+//
+//   expr    := sum ((XOR | XNOR) sum)*
+//   sum     := product (OR product)*
+//   product := factor (AND factor)*
+//   factor  := pin | '/(' expr ')' | '(' expr ')'
+pub(crate) fn parse_expr<I>(symbols: &Symbols, tokens: &mut Peekable<I>) -> Result<Expr, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let mut expr = parse_sum(symbols, tokens)?;
+    loop {
+        let is_xnor = match tokens.peek() {
+            Some((_, Token::Xor)) => false,
+            Some((_, Token::Xnor)) => true,
+            Some((_, token)) => match xor_word(token) {
+                Some(is_xnor) => is_xnor,
+                None => return Ok(expr),
+            },
+            None => return Ok(expr),
+        };
+        tokens.next();
+
+        let rhs = parse_sum(symbols, tokens)?;
+        expr = Expr::Xor(Box::new(expr), Box::new(rhs));
+        if is_xnor {
+            expr = Expr::Not(Box::new(expr));
+        }
+    }
+}
+
+fn parse_sum<I>(symbols: &Symbols, tokens: &mut Peekable<I>) -> Result<Expr, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let mut products = vec![parse_product(symbols, tokens)?];
+    while let Some((_, Token::Or)) = tokens.peek() {
+        tokens.next();
+        products.push(parse_product(symbols, tokens)?);
+    }
+
+    Ok(if products.len() == 1 {
+        products.pop().unwrap()
+    } else {
+        Expr::Or(products)
+    })
+}
+
+fn parse_product<I>(symbols: &Symbols, tokens: &mut Peekable<I>) -> Result<Expr, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let mut factors = vec![parse_factor(symbols, tokens)?];
+    while let Some((_, Token::And)) = tokens.peek() {
+        tokens.next();
+        factors.push(parse_factor(symbols, tokens)?);
+    }
+
+    Ok(if factors.len() == 1 {
+        factors.pop().unwrap()
+    } else {
+        Expr::And(factors)
+    })
+}
+
+fn parse_factor<I>(symbols: &Symbols, tokens: &mut Peekable<I>) -> Result<Expr, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let (line_num, token) = next_or_fail(tokens, ErrorCode::BadEOL)?;
+    match token {
+        Token::Item((named_pin, suffix)) => {
+            if suffix != Suffix::None {
+                err(line_num, ErrorCode::BadPinSuffix)
+            } else if let Some(range) = named_pin.bus {
+                parse_bus_compare(symbols, line_num, &named_pin, range, tokens)
+            } else if let Some(expr) = symbols.defines.get(&named_pin.name) {
+                Ok(if named_pin.neg {
+                    Expr::Not(Box::new(expr.clone()))
+                } else {
+                    expr.clone()
+                })
+            } else {
+                let pin = at_line(line_num, lookup_pin(symbols, &named_pin))?;
+                Ok(Expr::Pin(pin))
+            }
+        }
+        Token::Expr(expr) => Ok(expr),
+        Token::Not => {
+            let expr = parse_factor(symbols, tokens)?;
+            Ok(Expr::Not(Box::new(expr)))
+        }
+        Token::LParen => {
+            let expr = parse_expr(symbols, tokens)?;
+            match tokens.next() {
+                Some((_, Token::RParen)) => Ok(expr),
+                Some((line_num, _)) => err(line_num, ErrorCode::BadToken { expected: "')'" }),
+                None => err(line_num, ErrorCode::UnmatchedParen),
+            }
+        }
+        _ => err(line_num, ErrorCode::BadToken { expected: "pin" }),
+    }
+}
+
+// Parse the rest of a comparison of a bus against a constant (e.g.
+// 'A[15..12] == 0xA'), and convert it to a product of the bus pins.
+fn parse_bus_compare<I>(
+    symbols: &Symbols,
+    line_num: Pos,
+    named_pin: &NamedPin,
+    range: (usize, usize),
+    tokens: &mut Peekable<I>,
+) -> Result<Expr, Error>
+where
+    I: Iterator<Item = (Pos, Token)>,
+{
+    let not_equal = match tokens.next() {
+        Some((_, Token::EqEq)) => false,
+        Some((_, Token::NotEq)) => true,
+        Some((line_num, _)) => {
+            return err(
+                line_num,
+                ErrorCode::BadToken {
+                    expected: "== or !=",
+                },
+            )
+        }
+        None => {
+            return err(
+                line_num,
+                ErrorCode::BadToken {
+                    expected: "== or !=",
+                },
+            )
+        }
+    };
+
+    let (pos, value) = match tokens.next() {
+        Some((pos, Token::Number(num))) => (pos, at_line(pos, parse_number(&num))?),
+        Some((pos, _)) => return err(pos, ErrorCode::BadToken { expected: "number" }),
+        None => return err(line_num, ErrorCode::BadToken { expected: "number" }),
+    };
+
+    let bits = bus_bits(&named_pin.name, range);
+    if bits.len() < 64 && value >> bits.len() != 0 {
+        return err(
+            pos,
+            ErrorCode::BusValueTooWide {
+                value,
+                width: bits.len(),
+            },
+        );
+    }
+
+    let mut factors = Vec::new();
+    for (name, significance) in bits.into_iter() {
+        let bit = NamedPin {
+            name,
+            neg: (value >> significance) & 1 == 0,
+            bus: None,
+        };
+        factors.push(Expr::Pin(at_line(line_num, lookup_pin(symbols, &bit))?));
+    }
+
+    let expr = Expr::And(factors);
+    Ok(if named_pin.neg != not_equal {
+        Expr::Not(Box::new(expr))
+    } else {
+        expr
+    })
+}
+
+// Add a row's worth of pins to the pin map.
+fn extend_pin_map(
+    pin_map: &mut HashMap<String, Pin>,
+    chip: Chip,
+    row_num: usize,
+    pins: &[(String, bool)],
+) -> Result<(), ErrorCode> {
+    let first_pin = 1 + row_num * chip.num_pins() / 2;
+    for ((name, neg), pin_num) in pins.iter().cloned().zip(first_pin..) {
+        add_pin(pin_map, chip, pin_num, name, neg)?;
+    }
+
+    Ok(())
+}
+
+// Add a single pin to the pin map, checking the power pins are in
+// the right place and names aren't reused.
+pub(crate) fn add_pin(
+    pin_map: &mut HashMap<String, Pin>,
+    chip: Chip,
+    pin_num: usize,
+    name: String,
+    neg: bool,
+) -> Result<(), ErrorCode> {
+    let num_pins = chip.num_pins();
+    if pin_num == num_pins && (name.as_str(), neg) != ("VCC", false) {
+        return Err(ErrorCode::InvalidPowerPinName {
+            pin: pin_num,
+            name: "VCC",
+        });
+    }
+    if pin_num == num_pins / 2 && (name.as_str(), neg) != ("GND", false) {
+        return Err(ErrorCode::InvalidPowerPinName {
+            pin: pin_num,
+            name: "GND",
+        });
+    }
+    if name == "VCC" && pin_num != num_pins {
+        return Err(ErrorCode::InvalidPowerPinLocation {
+            pin: pin_num,
+            name: "VCC",
+            expected_pin: num_pins,
+        });
+    }
+    if name == "GND" && pin_num != num_pins / 2 {
+        return Err(ErrorCode::InvalidPowerPinLocation {
+            pin: pin_num,
+            name: "GND",
+            expected_pin: num_pins / 2,
+        });
+    }
+    if name != "NC" {
+        if pin_map.contains_key(&name) {
+            return Err(ErrorCode::RepeatedPinName { name });
+        }
+
+        if chip.family() == Chip::GAL22V10 {
+            // parse returns Ok if name is "AR" or "SP"
+            if let Ok(term) = name.parse() {
+                return Err(ErrorCode::ReservedPinName { term });
+            }
+        }
+
+        pin_map.insert(name, Pin { pin: pin_num, neg });
+    }
+
+    Ok(())
+}
+
+// Map from pin names to pins.
+pub(crate) type PinMap = HashMap<String, Pin>;
+
+// A declaration of pin numbers, and the names given to them.
+pub(crate) type PinDecl = (LineNum, Vec<usize>, Vec<(String, bool)>);
+
+// Build the pin list and symbol table from the pin declarations.
+// Undeclared pins are left unconnected.
+pub(crate) fn assign_pins(
+    chip: Chip,
+    decls: Vec<PinDecl>,
+) -> Result<(Vec<(String, bool)>, PinMap), Error> {
+    let num_pins = chip.num_pins();
+    let mut pins = vec![("NC".to_string(), false); num_pins];
+    let mut pin_map = HashMap::new();
+    for (line_num, pin_nums, names) in decls.into_iter() {
+        if pin_nums.len() != names.len() {
+            return err(
+                line_num,
+                ErrorCode::BadPinCount {
+                    found: names.len(),
+                    expected: pin_nums.len(),
+                },
+            );
+        }
+        for (pin_num, (name, neg)) in pin_nums.into_iter().zip(names) {
+            if pin_num < 1 || pin_num > num_pins {
+                return err(
+                    line_num,
+                    ErrorCode::BadPinNumber {
+                        pin: pin_num,
+                        max: num_pins,
+                    },
+                );
+            }
+            if pins[pin_num - 1].0 != "NC" {
+                return err(line_num, ErrorCode::RepeatedPinNumber { pin: pin_num });
+            }
+            at_line(
+                line_num,
+                add_pin(&mut pin_map, chip, pin_num, name.clone(), neg),
+            )?;
+            pins[pin_num - 1] = (name, neg);
+        }
+    }
+
+    // Power pins don't need declaring.
+    for (pin_num, name) in [(num_pins / 2, "GND"), (num_pins, "VCC")] {
+        if pins[pin_num - 1].0 == "NC" {
+            pins[pin_num - 1] = (name.to_string(), false);
+            at_line(
+                1,
+                add_pin(&mut pin_map, chip, pin_num, name.to_string(), false),
+            )?;
+        }
+    }
+
+    Ok((pins, pin_map))
+}
+
+// The rest of the pipeline just wants string names.
+pub(crate) fn pin_names(pins: Vec<(String, bool)>) -> Vec<String> {
+    pins.into_iter()
+        .map(|(name, neg)| if neg { format!("/{}", name) } else { name })
+        .collect()
+}
+
+// Skip the rest of a TABLE, SEQUENCE or VECTORS block after an error
+// in it, so that we can carry on parsing after the closing '}'.
+fn skip_block<L>(lines: &mut L)
+where
+    L: Iterator<Item = Result<Vec<(Pos, Token)>, Error>>,
+{
+    for line in lines {
+        if matches!(line.as_deref(), Ok([(_, Token::RBrace)])) {
+            break;
+        }
+    }
+}
+
+// Parse a whole file. Errors in the chip, signature and pin lines stop
+// the parse, but after those we carry on past bad equations so that
+// all their errors are reported.
+pub(crate) fn parse_core<'a, I>(line_iter: I) -> Result<Content, Vec<Error>>
+where
+    I: Iterator<Item = (LineNum, &'a str)>,
+{
+    // Split off the comments and DESCRIPTION section, which are kept
+    // for the output files.
+    let (lines, comments, description) = split_notes(line_iter);
+    let mut line_iter = lines.into_iter();
+
+    // Chip type and signature must be on first two lines.
+    let chip = parse_chip(&mut line_iter).map_err(|e| vec![e])?;
+    let signature = parse_signature(&mut line_iter).map_err(|e| vec![e])?;
+
+    // We now ignore blank lines.
+    let mut line_iter = line_iter.filter(|(_, x)| !x.is_empty()).peekable();
+
+    // Pins are either given as 'PIN n = name' lines, or as two lines
+    // listing all the pins.
+    let (pins, pin_map) = parse_all_pins(chip, &mut line_iter).map_err(|e| vec![e])?;
 
     // We tokenise the lines first, as the equation parser will want
     // to look ahead onto the token starting the next line (not yet
     // implemented).
+    let mut symbols = Symbols::new(chip, pin_map);
     let mut equations = Vec::new();
-    for tokens_or_err in tokenised_lines(line_iter) {
-        let tokens = tokens_or_err?;
-        equations.push(parse_equation(chip, &pin_map, &mut tokens.into_iter())?);
+    // The last line of each multi-line equation, to find its comments.
+    let mut last_lines = HashMap::new();
+    let mut vectors = Vec::new();
+    let mut mode = None;
+    let mut errors = Vec::new();
+    let mut lines = tokenised_lines(line_iter);
+    while let Some(tokens_or_err) = lines.next() {
+        let tokens = match tokens_or_err {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let res = if is_directive(&tokens, "MODE") {
+            let line_num = tokens[0].0.line;
+            let mut tokens = tokens.into_iter().skip(1);
+            parse_mode(chip, line_num, &mut tokens).and_then(|m| match mode.replace(m) {
+                Some(_) => err(line_num, ErrorCode::RepeatedMode),
+                None => Ok(()),
+            })
+        } else if is_directive(&tokens, "DEFINE") {
+            let mut tokens = tokens.into_iter().skip(1).peekable();
+            parse_define(&mut symbols, &mut tokens)
+        } else if is_directive(&tokens, "TABLE") {
+            let line_num = tokens[0].0.line;
+            let mut tokens = tokens.into_iter().skip(1);
+            parse_table(&symbols, line_num, &mut tokens, &mut lines)
+                .map(|mut table| equations.append(&mut table))
+                .inspect_err(|_| skip_block(&mut lines))
+        } else if is_directive(&tokens, "SEQUENCE") {
+            let line_num = tokens[0].0.line;
+            let mut tokens = tokens.into_iter().skip(1);
+            parse_sequence(&symbols, line_num, &mut tokens, &mut lines)
+                .map(|mut sequence| equations.append(&mut sequence))
+                .inspect_err(|_| skip_block(&mut lines))
+        } else if is_directive(&tokens, "VECTORS") {
+            let line_num = tokens[0].0.line;
+            let mut tokens = tokens.into_iter().skip(1);
+            parse_vectors(&symbols, line_num, &mut tokens, &mut lines)
+                .map(|mut block| vectors.append(&mut block))
+                .inspect_err(|_| skip_block(&mut lines))
+        } else {
+            let last_line = tokens.last().map(|(pos, _)| pos.line);
+            let mut tokens = tokens.into_iter().peekable();
+            parse_equation(&symbols, &mut tokens).map(|eqn| {
+                if let Some(last_line) = last_line {
+                    last_lines.insert(eqn.line_num, last_line);
+                }
+                equations.push(eqn)
+            })
+        };
+        if let Err(e) = res {
+            errors.push(e);
+        }
     }
 
-    // The rest of the pipeline just wants string names.
-    let pin_names = pins
-        .iter()
-        .map(|(pin_name, neg)| {
-            let mut full_name = if *neg {
-                String::from("/")
-            } else {
-                String::new()
-            };
-            full_name.push_str(pin_name);
-            full_name
-        })
-        .collect::<Vec<String>>();
-
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let notes = Notes {
+        description,
+        comments: equation_comments(&comments, &equations, &last_lines),
+        vectors,
+    };
     Ok(Content {
         chip,
         sig: signature,
-        pins: pin_names,
+        pins: pin_names(pins),
         eqns: equations,
+        mode,
+        notes,
     })
 }
 
-fn err<T>(line_num: LineNum, error_code: ErrorCode) -> Result<T, Error> {
-    Err(Error {
-        code: error_code,
-        line: line_num,
-    })
+// A comment, and whether it's on a line by itself.
+type Comment = (LineNum, String, bool);
+
+// Remove comments (and end-of-line whitespace) from the lines, and
+// stop at the DESCRIPTION line, if any. Unlike galasm, we don't
+// *require* a DESCRIPTION line. We keep leading whitespace so that
+// token columns are right. Returns the lines, the comments and the
+// description.
+fn split_notes<'a, I>(line_iter: I) -> (Vec<(LineNum, &'a str)>, Vec<Comment>, Vec<String>)
+where
+    I: Iterator<Item = (LineNum, &'a str)>,
+{
+    let mut lines = Vec::new();
+    let mut comments = Vec::new();
+    let mut description = Vec::new();
+    let mut line_iter = line_iter.peekable();
+    while let Some((line_num, line)) = line_iter.next() {
+        // The chip and signature lines come first, whatever they say.
+        let code = if lines.len() == 1 {
+            remove_sig_comment(line)
+        } else {
+            remove_comment(line)
+        }
+        .trim_end();
+        if lines.len() >= 2 && code.trim_start() == "DESCRIPTION" {
+            description.extend(line_iter.by_ref().map(|(_, x)| x.trim_end().to_string()));
+            break;
+        }
+        if let Some(comment) = line
+            .get(code.len()..)
+            .and_then(|x| x.trim().strip_prefix(';'))
+        {
+            comments.push((line_num, comment.trim().to_string(), code.trim().is_empty()));
+        }
+        lines.push((line_num, code));
+    }
+
+    // Drop blank lines from the start and end of the description.
+    while description.last().is_some_and(|x| x.is_empty()) {
+        description.pop();
+    }
+    let start = description.iter().take_while(|x| x.is_empty()).count();
+    description.drain(..start);
+
+    (lines, comments, description)
 }
 
-pub fn parse(file_name: &str) -> Result<Content, Error> {
-    let data = fs::read_to_string(file_name).expect("Unable to read file");
-    parse_core((1..).zip(data.lines())).map_err(|e| {
-        if e.line == EOF_LINE {
-            Error {
-                line: data.lines().count(),
-                ..e
+// Gather the comments for each equation: any comment-only lines
+// directly above it, and any comments on the lines it spans.
+fn equation_comments(
+    comments: &[Comment],
+    eqns: &[Equation],
+    last_lines: &HashMap<LineNum, LineNum>,
+) -> HashMap<LineNum, Vec<String>> {
+    let mut res = HashMap::new();
+    for eqn in eqns.iter() {
+        let line_num = eqn.line_num;
+        let mut above = Vec::new();
+        for l in (1..line_num).rev() {
+            match comments.iter().find(|(c_line, _, _)| *c_line == l) {
+                Some((_, text, true)) => above.push(text.clone()),
+                _ => break,
             }
-        } else {
-            e
         }
+        above.reverse();
+        let last_line = last_lines.get(&line_num).copied().unwrap_or(line_num);
+        above.extend(
+            comments
+                .iter()
+                .filter(|(c_line, _, _)| (line_num..=last_line).contains(c_line))
+                .map(|(_, text, _)| text.clone()),
+        );
+        if !above.is_empty() {
+            res.insert(line_num, above);
+        }
+    }
+    res
+}
+
+fn parse_all_pins<'a, I>(
+    chip: Chip,
+    line_iter: &mut Peekable<I>,
+) -> Result<(Vec<(String, bool)>, PinMap), Error>
+where
+    I: Iterator<Item = (LineNum, &'a str)>,
+{
+    if line_iter.peek().is_some_and(|line| is_pin_decl(*line)) {
+        let mut decls = Vec::new();
+        while let Some(line) = line_iter.next_if(|line| is_pin_decl(*line)) {
+            decls.push(parse_pin_decl(line)?);
+        }
+        assign_pins(chip, decls)
+    } else {
+        let mut pin_map = HashMap::new();
+        let mut pins = parse_pins(&mut pin_map, chip, 0, line_iter)?;
+        let mut pins2 = parse_pins(&mut pin_map, chip, 1, line_iter)?;
+        pins.append(&mut pins2);
+        Ok((pins, pin_map))
+    }
+}
+
+pub(crate) fn err<T, P: Into<Pos>>(pos: P, error_code: ErrorCode) -> Result<T, Error> {
+    at_line(pos, Err(error_code))
+}
+
+pub(crate) fn read_input(file_name: &str) -> Result<String, Error> {
+    fs::read_to_string(file_name).or_else(|e| {
+        err(
+            NO_LINE,
+            ErrorCode::ReadFailed {
+                file: file_name.to_string(),
+                err: e.to_string(),
+            },
+        )
     })
 }
+
+pub fn parse(file_name: &str) -> Result<Content, Vec<Error>> {
+    let data = read_input(file_name).map_err(|e| vec![e])?;
+    parse_str(&data)
+}
+
+pub fn parse_str(data: &str) -> Result<Content, Vec<Error>> {
+    parse_core((1..).zip(data.lines()))
+        .map_err(|errors| errors.into_iter().map(|e| fix_eof_line(e, data)).collect())
+}
+
+// Errors found at the end of the input are reported against the last
+// line.
+fn fix_eof_line(e: Error, data: &str) -> Error {
+    if e.line == EOF_LINE {
+        Error {
+            line: data.lines().count(),
+            ..e
+        }
+    } else {
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_expansion() {
+        assert_eq!(
+            bus_bits("A", (15, 12)),
+            vec![
+                ("A15".to_string(), 3),
+                ("A14".to_string(), 2),
+                ("A13".to_string(), 1),
+                ("A12".to_string(), 0),
+            ]
+        );
+        assert_eq!(
+            bus_bits("D", (0, 1)),
+            vec![("D0".to_string(), 0), ("D1".to_string(), 1)]
+        );
+
+        // Ranges are capped at the width of the largest chip.
+        assert!(tokenise((1, "A[23..0]")).is_ok());
+        assert!(matches!(
+            tokenise((1, "A[24..0]")).unwrap_err().code,
+            ErrorCode::BadBusRange
+        ));
+        assert!(matches!(
+            tokenise((1, "A[100000000..0]")).unwrap_err().code,
+            ErrorCode::BadBusRange
+        ));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("10").unwrap(), 10);
+        assert_eq!(parse_number("0x1f").unwrap(), 31);
+        assert_eq!(parse_number("0b101").unwrap(), 5);
+        assert!(parse_number("0xg").is_err());
+        assert!(parse_number("12a").is_err());
+    }
+
+    #[test]
+    fn pin_decls() {
+        assert!(is_pin_decl((1, "PIN 14 = /CS_ROM")));
+        assert!(!is_pin_decl((1, "PIN A B C")));
+        assert_eq!(
+            parse_pin_decl((1, "PIN 14 = /CS_ROM")).unwrap(),
+            (1, vec![14], vec![("CS_ROM".to_string(), true)])
+        );
+        assert!(parse_pin_decl((1, "PIN 14 = A B")).is_err());
+    }
+
+    #[test]
+    fn underscores() {
+        let pin = |name: &str, neg| NamedPin {
+            name: name.to_string(),
+            neg,
+            bus: None,
+        };
+        let tokens = tokenise((1, "CS_ROM = /A_1")).unwrap();
+        assert_eq!(
+            tokens
+                .into_iter()
+                .map(|(_, token)| token)
+                .collect::<Vec<_>>(),
+            vec![
+                Token::Item((pin("CS_ROM", false), Suffix::None)),
+                Token::Equals,
+                Token::Item((pin("A_1", true), Suffix::None)),
+            ]
+        );
+        // Names must still start with a letter.
+        assert!(tokenise((1, "_A = B")).is_err());
+    }
+
+    #[test]
+    fn table_bits() {
+        let mut row = tokenise((1, "01 - =>")).unwrap().into_iter();
+        assert_eq!(
+            parse_table_bits(1, &mut row, 3, "input").unwrap(),
+            vec![Some(false), Some(true), None]
+        );
+
+        let mut row = tokenise((1, "012")).unwrap().into_iter();
+        assert!(parse_table_bits(1, &mut row, 3, "input").is_err());
+        let mut row = tokenise((1, "01")).unwrap().into_iter();
+        assert!(parse_table_bits(1, &mut row, 3, "output").is_err());
+    }
+
+    #[test]
+    fn defines() {
+        let header = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n";
+        let rhs = |eqns: &str| match parse_str(&format!("{}{}", header, eqns)) {
+            Ok(content) => content
+                .eqns
+                .into_iter()
+                .map(|eqn| eqn.rhs)
+                .collect::<Vec<_>>(),
+            Err(_) => panic!("parse failed"),
+        };
+
+        // DEFINEs are substituted, may use earlier DEFINEs, and may be
+        // negated.
+        assert_eq!(
+            rhs("DEFINE SEL = /A * B\n\
+                 DEFINE BOTH = SEL + C\n\
+                 R = SEL\n\
+                 Q = /BOTH * D\n\
+                 P = BOTH\n"),
+            rhs("R = /A * B\n\
+                 Q = /((/A * B) + C) * D\n\
+                 P = (/A * B) + C\n")
+        );
+
+        let errors = |eqns: &str| match parse_str(&format!("{}{}", header, eqns)) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => errors
+                .into_iter()
+                .map(|e| (e.line, e.code))
+                .collect::<Vec<_>>(),
+        };
+        assert!(matches!(
+            errors(
+                "DEFINE SEL = A\n\
+                 DEFINE SEL = B\n\
+                 DEFINE C = B\n\
+                 DEFINE X = FOO\n\
+                 R = LATER\n\
+                 DEFINE LATER = A\n\
+                 SEL = B\n"
+            )[..],
+            [
+                (6, ErrorCode::RepeatedPinName { .. }),
+                (7, ErrorCode::RepeatedPinName { .. }),
+                (8, ErrorCode::UnknownPin { .. }),
+                (9, ErrorCode::UnknownPin { .. }),
+                (11, ErrorCode::AssignedDefine { .. }),
+            ]
+        ));
+    }
+
+    #[test]
+    fn tables() {
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n\
+                    TABLE A B => R Q {\n\
+                        1 - => - 0\n\
+                        1 1 => 0 0\n\
+                        0 1 => 1 -\n\
+                        0 0 => 0 1\n\
+                    }\n";
+        let content = match parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        let pin = |pin, neg| Expr::Pin(Pin { pin, neg });
+        let rhs = content
+            .eqns
+            .iter()
+            .map(|eqn| eqn.rhs.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rhs,
+            vec![
+                // The don't care in the first row can't make R true
+                // where the second row says it's 0.
+                Expr::Or(vec![Expr::And(vec![pin(1, true), pin(2, false)])]),
+                // But the don't care in the third row lets Q = /A.
+                Expr::Or(vec![Expr::And(vec![pin(1, true)])]),
+            ]
+        );
+    }
+
+    #[test]
+    fn state_bits() {
+        assert_eq!(
+            state_bit_values(6, 4).collect::<Vec<_>>(),
+            vec![false, true, true, false]
+        );
+
+        let mut bit_terms = vec![Vec::new(); 2];
+        let cond = Expr::Pin(Pin { pin: 2, neg: false });
+        add_state_terms(&mut bit_terms, 1, cond.clone());
+        assert_eq!(bit_terms, vec![vec![], vec![cond]]);
+    }
+
+    // Evaluate an expression, given the values of the pins.
+    fn eval(expr: &Expr, values: &HashMap<usize, bool>) -> bool {
+        match expr {
+            Expr::Pin(pin) => values[&pin.pin] != pin.neg,
+            Expr::Not(expr) => !eval(expr, values),
+            Expr::And(exprs) => exprs.iter().all(|expr| eval(expr, values)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| eval(expr, values)),
+            Expr::Xor(a, b) => eval(a, values) != eval(b, values),
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n\
+                    SEQUENCE R Q {\n\
+                        STATE 0\n\
+                            IF A NEXT 1\n\
+                        STATE 1\n\
+                            IF B NEXT 2\n\
+                            IF A NEXT 0\n\
+                        STATE 2\n\
+                            IF A NEXT 1\n\
+                    }\n";
+        let content = match parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        let lhs = content
+            .eqns
+            .iter()
+            .map(|eqn| eqn.lhs.clone())
+            .collect::<Vec<_>>();
+        let output = |pin| LHS::Pin((Pin { pin, neg: false }, Suffix::R));
+        assert_eq!(lhs, vec![output(19), output(18)]);
+
+        for state in 0..4 {
+            for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+                let values =
+                    HashMap::from([(1, a), (2, b), (19, state & 2 != 0), (18, state & 1 != 0)]);
+                let next = content
+                    .eqns
+                    .iter()
+                    .fold(0, |acc, eqn| acc * 2 + eval(&eqn.rhs, &values) as u64);
+                let expected = match state {
+                    0 if a => 1,
+                    1 if b => 2,
+                    1 if a => 0,
+                    2 if a => 1,
+                    // No transition matches, so stay put.
+                    0..=2 => state,
+                    // State 3 isn't listed, so goes to 0.
+                    _ => 0,
+                };
+                assert_eq!(next, expected, "state {}, A = {}, B = {}", state, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn all_errors() {
+        let data = "GAL16V8\nSig\n\
+                    I1 I2 I3 I4 I5 I6 I7 I8 I9 GND\n\
+                    I11 O1 O2 O3 O4 O5 O6 O7 O8 VCC\n\
+                    O1 = I1 * FOO\n\
+                    TABLE I1 I2 => O2 {\n\
+                    0 x => 1\n\
+                    }\n\
+                    O3 = I2\n\
+                    O4 = BAR\n";
+        match parse_str(data) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => {
+                let lines = errors.iter().map(|e| e.line).collect::<Vec<_>>();
+                assert_eq!(lines, vec![5, 7, 10]);
+            }
+        }
+    }
+
+    #[test]
+    fn error_columns() {
+        let e = tokenise((3, "  A * B ? C")).unwrap_err();
+        assert_eq!((e.line, e.cols), (3, Some((9, 10))));
+        assert_eq!(
+            e.snippet("\n\n  A * B ? C\n").unwrap(),
+            "  |\n3 |   A * B ? C\n  |         ^\n"
+        );
+
+        let tokens = tokenise((1, "O = /FOO.R")).unwrap();
+        assert_eq!(tokens[2].0.cols, Some((5, 11)));
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(
+            parse_signature_value("0x00fF12").unwrap(),
+            vec![0x00, 0xff, 0x12]
+        );
+        assert_eq!(parse_signature_value("\"A;B \"").unwrap(), b"A;B ".to_vec());
+        assert_eq!(parse_signature_value("rev 2").unwrap(), b"rev 2".to_vec());
+        for bad in ["0x", "0x123", "0x+1", "0xgg", "\"open"].iter() {
+            assert!(parse_signature_value(bad).is_err());
+        }
+
+        let mut lines = vec![(2, "SIGNATURE = 0x0102")].into_iter();
+        assert_eq!(parse_signature(&mut lines).unwrap(), vec![1, 2]);
+        // Without the '=', it's just text.
+        let mut lines = vec![(2, "SIGNATURE 1")].into_iter();
+        assert_eq!(
+            parse_signature(&mut lines).unwrap(),
+            b"SIGNATURE 1".to_vec()
+        );
+
+        // A ';' in quotes is part of the signature, not a comment.
+        let data = "GAL16V8\nSIGNATURE = \"A;B\" ; rev\n\
+                    A B C D E F G H I GND\nJ K L M N O P Q R VCC\nR = A\n";
+        let content = match parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        assert_eq!(content.sig, b"A;B".to_vec());
+    }
+
+    #[test]
+    fn vectors() {
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n\
+                    R = A\nVECTORS A B => R {\n  0 - => L\n  C1 => z\n}\n";
+        let content = match parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        assert_eq!(
+            content.notes.vectors,
+            vec!["0XXXXXXXXNXXXXXXXXLN", "C1XXXXXXXNXXXXXXXXZN"]
+        );
+
+        let data = data.replace("C1 => z", "C1 => K");
+        match parse_str(&data) {
+            Ok(_) => panic!("bad output accepted"),
+            Err(errors) => assert_eq!(errors[0].code.name(), "BadVectorBit"),
+        }
+
+        // Each pin may only be listed once.
+        for header in [
+            "VECTORS A B => A {",
+            "VECTORS A A => R {",
+            "VECTORS A B => R R {",
+        ] {
+            let data = data.replace("VECTORS A B => R {", header);
+            match parse_str(&data) {
+                Ok(_) => panic!("repeated pin accepted"),
+                Err(errors) => assert_eq!(errors[0].code.name(), "RepeatedVectorPin"),
+            }
+        }
+    }
+
+    #[test]
+    fn xor_keywords() {
+        let parse = |eqns: &str| {
+            let data = format!(
+                "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n{}",
+                eqns
+            );
+            match parse_str(&data) {
+                Ok(content) => content
+                    .eqns
+                    .into_iter()
+                    .map(|eqn| eqn.rhs)
+                    .collect::<Vec<_>>(),
+                Err(_) => panic!("parse failed"),
+            }
+        };
+        assert_eq!(
+            parse("R = A XOR B\nQ = A XNOR /B\n"),
+            parse("R = A $ B\nQ = A !$ /B\n")
+        );
+        // Including across lines.
+        assert_eq!(
+            parse("R = A XOR\n  B\nQ = A\n  XNOR /B\n"),
+            parse("R = A $ B\nQ = A !$ /B\n")
+        );
+
+        // They're only operators between operands, so can still be
+        // used as pins.
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P XNOR XOR VCC\n\
+                    XOR = XNOR XOR A\nXNOR = XOR\n";
+        let pin = |pin| Expr::Pin(Pin { pin, neg: false });
+        match parse_str(data) {
+            Ok(content) => {
+                assert_eq!(
+                    content.eqns[0].rhs,
+                    Expr::Xor(Box::new(pin(18)), Box::new(pin(1)))
+                );
+                assert_eq!(content.eqns[1].rhs, pin(19));
+            }
+            Err(_) => panic!("parse failed"),
+        }
+    }
+
+    #[test]
+    fn directive_pins() {
+        // Pins may share their names with directives.
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\n\
+                    J K MODE TABLE DEFINE SEQUENCE VECTORS Q R VCC\n\
+                    MODE = A\nTABLE = A\nDEFINE = A\nSEQUENCE = A\nVECTORS = A\n";
+        match parse_str(data) {
+            Ok(content) => assert_eq!(content.eqns.len(), 5),
+            Err(_) => panic!("parse failed"),
+        }
+    }
+
+    #[test]
+    fn notes() {
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n\
+                    ; Chip select\n; (active low)\n/R = A * B ; ROM\nQ = C\n\
+                    DESCRIPTION\n\nA test.\n\n";
+        let content = match parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        assert_eq!(content.notes.description, vec!["A test."]);
+        assert_eq!(
+            content.notes.comments.get(&7).unwrap(),
+            &vec!["Chip select", "(active low)", "ROM"]
+        );
+        assert_eq!(content.notes.comments.get(&8), None);
+
+        // Comments on continuation lines belong to the equation too.
+        let data = "GAL16V8\nSig\nA B C D E F G H I GND\nJ K L M N O P Q R VCC\n\
+                    R = A ; first\n  + B ; second\n; third\n  + C\nQ = C\n";
+        let content = match parse_str(data) {
+            Ok(content) => content,
+            Err(_) => panic!("parse failed"),
+        };
+        assert_eq!(
+            content.notes.comments.get(&5).unwrap(),
+            &vec!["first", "second", "third"]
+        );
+        assert_eq!(content.notes.comments.get(&9), None);
+    }
+}
@@ -0,0 +1,258 @@
+//
+// warnings.rs: Checks for suspicious designs
+//
+// Look for things that assemble fine, but probably aren't what was
+// meant: unused pins, undriven outputs used as inputs, and product
+// terms that are always false or repeated. Products are checked as
+// written, rather than after expansion into sum-of-products form, as
+// the expansion can itself create always-false products (e.g. from
+// XOR), which the minimiser then removes.
+//
+
+use std::collections::HashSet;
+
+use crate::{
+    blueprint::{Blueprint, PinMode},
+    chips::Chip,
+    errors::{Warning, WarningCode},
+    gal::Mode,
+    parser::{Content, Expr},
+};
+
+pub fn check(content: &Content, blueprint: &Blueprint) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    // The signature is silently truncated by galasm.
+    if content.sig.len() > 8 {
+        warnings.push(Warning {
+            code: WarningCode::SignatureTruncated {
+                len: content.sig.len(),
+            },
+            line: None,
+        });
+    }
+
+    for eqn in content.eqns.iter() {
+        check_products(&eqn.rhs, &mut |code| {
+            warnings.push(Warning {
+                code,
+                line: Some(eqn.line_num),
+            })
+        });
+    }
+
+    check_pins(content, blueprint, &mut warnings);
+
+    warnings
+}
+
+////////////////////////////////////////////////////////////////////////
+// Product checks
+//
+
+// Check every product in the expression, reporting each problem found.
+fn check_products<F>(expr: &Expr, report: &mut F)
+where
+    F: FnMut(WarningCode),
+{
+    match expr {
+        Expr::Pin(_) => (),
+        Expr::Not(inner) => check_products(inner, report),
+        Expr::And(_) => {
+            let mut factors = Vec::new();
+            product_factors(expr, &mut factors);
+            let pins = factors
+                .iter()
+                .filter_map(|factor| match factor {
+                    Expr::Pin(pin) => Some(pin),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if pins
+                .iter()
+                .any(|a| pins.iter().any(|b| a.pin == b.pin && a.neg != b.neg))
+            {
+                report(WarningCode::AlwaysFalseProduct);
+            }
+            for factor in factors.iter() {
+                check_products(factor, report);
+            }
+        }
+        Expr::Or(_) => {
+            let mut terms = Vec::new();
+            sum_terms(expr, &mut terms);
+            let mut seen = HashSet::new();
+            for product in terms.iter().filter_map(|term| pure_product(term)) {
+                if !seen.insert(product) {
+                    report(WarningCode::DuplicateProduct);
+                }
+            }
+            for term in terms.iter() {
+                check_products(term, report);
+            }
+        }
+        Expr::Xor(lhs, rhs) => {
+            check_products(lhs, report);
+            check_products(rhs, report);
+        }
+    }
+}
+
+// Collect the factors of a product, looking through nested products
+// (e.g. from DEFINEs).
+fn product_factors<'a>(expr: &'a Expr, factors: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::And(exprs) => {
+            for expr in exprs.iter() {
+                product_factors(expr, factors);
+            }
+        }
+        _ => factors.push(expr),
+    }
+}
+
+// Collect the terms of a sum, looking through nested sums.
+fn sum_terms<'a>(expr: &'a Expr, terms: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Or(exprs) => {
+            for expr in exprs.iter() {
+                sum_terms(expr, terms);
+            }
+        }
+        _ => terms.push(expr),
+    }
+}
+
+// If the expression is just a product of pins, return the pins as a
+// canonical set, for comparison.
+fn pure_product(expr: &Expr) -> Option<Vec<(usize, bool)>> {
+    let mut factors = Vec::new();
+    product_factors(expr, &mut factors);
+    let mut pins = factors
+        .into_iter()
+        .map(|factor| match factor {
+            Expr::Pin(pin) => Some((pin.pin, pin.neg)),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    pins.sort_unstable();
+    pins.dedup();
+    Some(pins)
+}
+
+////////////////////////////////////////////////////////////////////////
+// Pin checks
+//
+
+fn check_pins(content: &Content, blueprint: &Blueprint, warnings: &mut Vec<Warning>) {
+    let chip = content.chip;
+
+    // Pins used as inputs, with the first line each is used on.
+    let mut inputs = Vec::new();
+    for eqn in content.eqns.iter() {
+        let mut pins = Vec::new();
+        expr_pins(&eqn.rhs, &mut pins);
+        for pin in pins {
+            if !inputs.iter().any(|(p, _)| *p == pin) {
+                inputs.push((pin, eqn.line_num));
+            }
+        }
+    }
+    let registered = match blueprint.mode {
+        Some(mode) => mode == Mode::Registered,
+        None => blueprint
+            .olmcs
+            .iter()
+            .any(|olmc| matches!(olmc.output, Some((PinMode::Registered, _)))),
+    };
+
+    for (pin_num, name) in (1..).zip(content.pins.iter()) {
+        let name = name.trim_start_matches('/');
+        if ["NC", "GND", "VCC"].contains(&name) || is_dedicated(chip, registered, pin_num) {
+            continue;
+        }
+        let name = name.to_string();
+        let input_line = inputs
+            .iter()
+            .find(|(pin, _)| *pin == pin_num)
+            .map(|(_, line)| *line);
+
+        let is_driven = |olmc_num: usize| blueprint.olmcs[olmc_num].output.is_some();
+        let (code, line) = match (chip.pin_to_olmc(pin_num), input_line) {
+            (Some(olmc_num), Some(line)) if !is_driven(olmc_num) => {
+                (WarningCode::UndrivenFeedback { name }, Some(line))
+            }
+            (Some(olmc_num), None) if !is_driven(olmc_num) => {
+                (WarningCode::UnusedOutput { name }, None)
+            }
+            (None, None) => (WarningCode::UnusedPin { name }, None),
+            _ => continue,
+        };
+        warnings.push(Warning { code, line });
+    }
+}
+
+// Collect the pins used in an expression.
+fn expr_pins(expr: &Expr, pins: &mut Vec<usize>) {
+    match expr {
+        Expr::Pin(pin) => pins.push(pin.pin),
+        Expr::Not(inner) => expr_pins(inner, pins),
+        Expr::And(exprs) | Expr::Or(exprs) => {
+            for expr in exprs.iter() {
+                expr_pins(expr, pins);
+            }
+        }
+        Expr::Xor(lhs, rhs) => {
+            expr_pins(lhs, pins);
+            expr_pins(rhs, pins);
+        }
+    }
+}
+
+// Is the pin used for the clock or output enable, and so used even if
+// it's not in any equation?
+fn is_dedicated(chip: Chip, registered: bool, pin_num: usize) -> bool {
+    match chip {
+        Chip::GAL16V8 | Chip::GAL20V8 | Chip::ATF16V8B | Chip::ATF20V8B => {
+            registered && (pin_num == 1 || pin_num == chip.num_pins() / 2 + 1)
+        }
+        Chip::GAL22V10 | Chip::ATF22V10C => registered && pin_num == 1,
+        Chip::GAL20RA10 => pin_num == 1 || pin_num == 13,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gal::Pin;
+
+    fn p(pin: usize, neg: bool) -> Expr {
+        Expr::Pin(Pin { pin, neg })
+    }
+
+    fn codes(expr: &Expr) -> Vec<&'static str> {
+        let mut res = Vec::new();
+        check_products(expr, &mut |code| res.push(code.name()));
+        res
+    }
+
+    #[test]
+    fn products() {
+        // A * /A
+        assert_eq!(
+            codes(&Expr::And(vec![p(2, false), p(2, true)])),
+            vec!["AlwaysFalseProduct"]
+        );
+        // A * B + C + B * A
+        assert_eq!(
+            codes(&Expr::Or(vec![
+                Expr::And(vec![p(2, false), p(3, false)]),
+                p(4, false),
+                Expr::And(vec![p(3, false), p(2, false)]),
+            ])),
+            vec!["DuplicateProduct"]
+        );
+        // A $ A is left to the minimiser.
+        assert!(codes(&Expr::Xor(Box::new(p(2, false)), Box::new(p(2, false)))).is_empty());
+    }
+}
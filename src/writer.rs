@@ -14,9 +14,10 @@ use std::{
 };
 
 use crate::{
-    blueprint::OLMC,
+    blueprint::{Blueprint, OLMC},
     chips::Chip,
-    gal::{Mode, GAL},
+    gal::{Mode, Term, GAL},
+    parser::Notes,
 };
 
 #[derive(Debug)]
@@ -28,36 +29,54 @@ pub struct Config {
 }
 
 ////////////////////////////////////////////////////////////////////////
-// Main entry point for writing all the files is 'write_files'.
+// 'make_files' generates the contents of all the files, and
+// 'write_files' writes out the ones that are enabled.
 //
 
-fn write_file(base: &Path, ext: &str, buf: &str) -> Result<(), Error> {
-    let mut file = File::create(base.with_extension(ext).to_str().unwrap())?;
-    file.write_all(buf.as_bytes())?;
-    Ok(())
+// The generated files' contents.
+#[derive(Clone, Debug)]
+pub struct Files {
+    pub jedec: String,
+    pub fuse: String,
+    pub pin: String,
+    pub chip: String,
+}
+
+pub fn make_files(config: &Config, blueprint: &Blueprint, gal: &GAL) -> Files {
+    let pin_names = &blueprint.pins;
+    let olmcs = &blueprint.olmcs;
+    Files {
+        jedec: make_jedec(config, &blueprint.notes, gal),
+        fuse: make_fuse(pin_names, &FuseComments::new(blueprint), gal),
+        pin: make_pin(gal, pin_names, olmcs),
+        chip: make_chip(gal.chip, pin_names),
+    }
 }
 
-pub fn write_files(
-    file_name: &str,
-    config: &Config,
-    pin_names: &[String],
-    olmcs: &[OLMC],
-    gal: &GAL,
-) -> Result<(), Error> {
+// Write a file alongside the input, returning the file name and error
+// if it fails.
+fn write_file(base: &Path, ext: &str, buf: &str) -> Result<(), (String, Error)> {
+    let name = base.with_extension(ext);
+    File::create(&name)
+        .and_then(|mut file| file.write_all(buf.as_bytes()))
+        .map_err(|e| (name.to_string_lossy().into_owned(), e))
+}
+
+pub fn write_files(file_name: &str, config: &Config, files: &Files) -> Result<(), (String, Error)> {
     let base = PathBuf::from(file_name);
 
-    write_file(&base, "jed", &make_jedec(config, gal))?;
+    write_file(&base, "jed", &files.jedec)?;
 
     if config.gen_fuse {
-        write_file(&base, "fus", &make_fuse(pin_names, gal))?;
+        write_file(&base, "fus", &files.fuse)?;
     }
 
     if config.gen_pin {
-        write_file(&base, "pin", &make_pin(gal, pin_names, olmcs))?;
+        write_file(&base, "pin", &files.pin)?;
     }
 
     if config.gen_chip {
-        write_file(&base, "chp", &make_chip(gal.chip, pin_names))?;
+        write_file(&base, "chp", &files.chip)?;
     }
 
     Ok(())
@@ -110,7 +129,7 @@ struct FuseBuilder<'a> {
 }
 
 impl<'a> FuseBuilder<'a> {
-    fn new(buf: &mut String) -> FuseBuilder {
+    fn new(buf: &mut String) -> FuseBuilder<'_> {
         FuseBuilder {
             buf,
             checksum: CheckSummer::new(),
@@ -154,8 +173,11 @@ impl<'a> FuseBuilder<'a> {
 // Core function to generate a string of the JEDEC file, given the
 // config, fuses, etc.
 //
-// It's galasm-compatible.
-pub fn make_jedec(config: &Config, gal: &GAL) -> String {
+// It's galasm-compatible, apart from including the DESCRIPTION
+// section (if any) in the notes before the first field, and any test
+// vectors.
+pub fn make_jedec(config: &Config, notes: &Notes, gal: &GAL) -> String {
+    let description = &notes.description;
     let chip = gal.chip;
     let row_len = chip.num_cols();
 
@@ -165,6 +187,12 @@ pub fn make_jedec(config: &Config, gal: &GAL) -> String {
 
     let _ = writeln!(buf, "GAL-Assembler:  Galette {}", env!("CARGO_PKG_VERSION"));
     let _ = writeln!(buf, "Device:         {}\n", chip.name());
+    if !description.is_empty() {
+        for line in description.iter() {
+            let _ = writeln!(buf, "{}", jedec_note(line));
+        }
+        buf.push('\n');
+    }
     // Default value of gal_fuses
     buf.push_str("*F0\n");
 
@@ -178,6 +206,12 @@ pub fn make_jedec(config: &Config, gal: &GAL) -> String {
     // Number of fuses.
     let _ = writeln!(buf, "*QF{}", chip.total_size());
 
+    // Number of pins and test vectors, if there are any vectors.
+    if !notes.vectors.is_empty() {
+        let _ = writeln!(buf, "*QP{}", chip.num_pins());
+        let _ = writeln!(buf, "*QV{}", notes.vectors.len());
+    }
+
     {
         // Construct fuse matrix.
         let mut fuse_builder = FuseBuilder::new(&mut buf);
@@ -197,7 +231,7 @@ pub fn make_jedec(config: &Config, gal: &GAL) -> String {
 
         // XOR bits are interleaved with S1 bits on GAL22V10 (stored
         // in the 'ac1' field, as it's the same function).
-        if chip != Chip::GAL22V10 {
+        if chip.family() != Chip::GAL22V10 {
             fuse_builder.add(&gal.xor)
         } else {
             let bits = itertools::interleave(gal.xor.iter(), gal.ac1.iter());
@@ -206,7 +240,13 @@ pub fn make_jedec(config: &Config, gal: &GAL) -> String {
 
         fuse_builder.add(&gal.sig);
 
-        if (chip == Chip::GAL16V8) || (chip == Chip::GAL20V8) {
+        // We don't support the ATF22V10C's pin-controlled power-down,
+        // so its enable fuse is left at 0, keeping pin 4 an input.
+        if chip == Chip::ATF22V10C {
+            fuse_builder.add(&[false]);
+        }
+
+        if (chip.family() == Chip::GAL16V8) || (chip.family() == Chip::GAL20V8) {
             fuse_builder.add(&gal.ac1);
             fuse_builder.add(&gal.pt);
             fuse_builder.add(&[gal.syn]);
@@ -217,6 +257,10 @@ pub fn make_jedec(config: &Config, gal: &GAL) -> String {
         fuse_builder.checksum();
     }
 
+    for (i, vector) in notes.vectors.iter().enumerate() {
+        let _ = writeln!(buf, "*V{:04} {}", i + 1, vector);
+    }
+
     buf.push_str("*\n");
     buf.push('\x03');
 
@@ -226,6 +270,18 @@ pub fn make_jedec(config: &Config, gal: &GAL) -> String {
     buf
 }
 
+// Notes run up to the first '*', and the file must stay free of
+// control characters other than STX and ETX, so tidy up a line to be
+// put in the notes. This rewrites the user's text: control characters
+// are dropped, and each '*' becomes '&' (the same AND, in galasm
+// syntax), so e.g. 'Y = A * B' is noted as 'Y = A & B'.
+fn jedec_note(line: &str) -> String {
+    line.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '*' { '&' } else { c })
+        .collect()
+}
+
 fn file_checksum(data: &[u8]) -> u16 {
     data.iter().fold(0, |checksum: u16, byte| {
         checksum.wrapping_add(u16::from(*byte))
@@ -288,7 +344,7 @@ fn pin_type(gal: &GAL, olmcs: &[OLMC], i: usize) -> &'static str {
     } else if i == num_pins {
         "VCC"
     } else {
-        match chip {
+        match chip.family() {
             Chip::GAL16V8 | Chip::GAL20V8 if gal.get_mode() == Mode::Registered && i == 1 => {
                 "Clock"
             }
@@ -350,7 +406,60 @@ fn to_bit(bit: bool) -> char {
     }
 }
 
-fn make_fuse(pin_names: &[String], gal: &GAL) -> String {
+// The equation comments to go with each part of the fuse map.
+struct FuseComments<'a> {
+    olmcs: Vec<Vec<&'a str>>,
+    ar: Vec<&'a str>,
+    sp: Vec<&'a str>,
+}
+
+impl<'a> FuseComments<'a> {
+    fn new(blueprint: &'a Blueprint) -> Self {
+        let notes = &blueprint.notes;
+        let olmcs = blueprint
+            .olmcs
+            .iter()
+            .map(|olmc| {
+                let terms = [
+                    olmc.output.as_ref().map(|(_, term)| term),
+                    olmc.tri_con.as_ref(),
+                    olmc.clock.as_ref(),
+                    olmc.arst.as_ref(),
+                    olmc.aprst.as_ref(),
+                ];
+                term_comments(notes, terms.iter().flatten().copied())
+            })
+            .collect();
+        FuseComments {
+            olmcs,
+            ar: term_comments(notes, blueprint.ar.iter()),
+            sp: term_comments(notes, blueprint.sp.iter()),
+        }
+    }
+}
+
+// Find the comments on the equations the terms came from. A term may
+// come from a TABLE or SEQUENCE shared with other outputs, so we avoid
+// repeating ourselves within an OLMC.
+fn term_comments<'a, 'b, I>(notes: &'a Notes, terms: I) -> Vec<&'a str>
+where
+    I: Iterator<Item = &'b Term>,
+{
+    terms
+        .filter_map(|term| notes.comments.get(&term.line_num))
+        .flatten()
+        .map(String::as_str)
+        .unique()
+        .collect()
+}
+
+fn write_comments(buf: &mut String, comments: &[&str]) {
+    for comment in comments.iter() {
+        let _ = write!(buf, "\n; {}", comment);
+    }
+}
+
+fn make_fuse(pin_names: &[String], comments: &FuseComments, gal: &GAL) -> String {
     // This function relies on detailed knowledge of the ordering of
     // rows in the fuse map vs. OLMCs vs. pins. It's brittle, but
     // no-one's changing the hardware layout. :)
@@ -364,8 +473,9 @@ fn make_fuse(pin_names: &[String], gal: &GAL) -> String {
     let mut row = 0;
 
     // AR for the 22V10
-    if chip == Chip::GAL22V10 {
+    if chip.family() == Chip::GAL22V10 {
         buf.push_str("\n\nAR");
+        write_comments(&mut buf, &comments.ar);
         make_row(&mut buf, &mut row, row_len, &gal.fuses);
     }
 
@@ -374,9 +484,9 @@ fn make_fuse(pin_names: &[String], gal: &GAL) -> String {
         let xor = to_bit(gal.xor[last_olmc - pin]);
         let ac1 = to_bit(gal.ac1[last_olmc - pin]);
         let flags = match chip {
-            Chip::GAL16V8 => format!("XOR = {:>1}   AC1 = {:>1}", xor, ac1),
-            Chip::GAL20V8 => format!("XOR = {:>1}   AC1 = {:>1}", xor, ac1),
-            Chip::GAL22V10 => format!("S0 = {:>1}   S1 = {:>1}", xor, ac1),
+            Chip::GAL16V8 | Chip::ATF16V8B => format!("XOR = {:>1}   AC1 = {:>1}", xor, ac1),
+            Chip::GAL20V8 | Chip::ATF20V8B => format!("XOR = {:>1}   AC1 = {:>1}", xor, ac1),
+            Chip::GAL22V10 | Chip::ATF22V10C => format!("S0 = {:>1}   S1 = {:>1}", xor, ac1),
             Chip::GAL20RA10 => format!("S0 = {:>1}", xor),
         };
         let _ = write!(
@@ -386,6 +496,10 @@ fn make_fuse(pin_names: &[String], gal: &GAL) -> String {
             pin_names[pin - 1],
             &flags
         );
+        // (The blueprint's OLMCs run the other way.)
+        if let Some(olmc_num) = chip.pin_to_olmc(pin) {
+            write_comments(&mut buf, &comments.olmcs[olmc_num]);
+        }
 
         for _ in 0..chip.num_rows_for_olmc(olmc) {
             // Print all fuses of an OLMC
@@ -396,8 +510,9 @@ fn make_fuse(pin_names: &[String], gal: &GAL) -> String {
     }
 
     // SP for the 22V10
-    if chip == Chip::GAL22V10 {
+    if chip.family() == Chip::GAL22V10 {
         buf.push_str("\n\nSP");
+        write_comments(&mut buf, &comments.sp);
         make_row(&mut buf, &mut row, row_len, &gal.fuses);
     }
 
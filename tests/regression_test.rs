@@ -15,6 +15,12 @@ use std::process::Command;
 use anyhow::{bail, Result};
 use test_bin::get_test_bin;
 
+// Scratch directories for the generated files, kept out of the source
+// tree.
+const SUCCESS_TEMP: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/test_temp_success");
+const SECURITY_TEMP: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/test_temp_security");
+const MINIMISED_TEMP: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/test_temp_minimised");
+
 fn ensure_dir_exists(name: &str) -> Result<()> {
     if Path::new(name).exists() {
         remove_dir_all(name)?;
@@ -71,7 +77,7 @@ fn check_invocation_failed(name: &str, messages: &HashMap<&str, &str>, res: std:
             name,
             *messages
                 .get(name)
-                .expect(&format!("No known error message for '{}'", name))
+                .unwrap_or_else(|| panic!("No known error message for '{}'", name))
         ),
         "'{:?}' produced unexpected output to stderr",
         name
@@ -90,7 +96,7 @@ fn ensure_contains(
     }
 
     let mut missing_names = contained_names
-        .difference(&containing_names)
+        .difference(containing_names)
         .collect::<Vec<_>>();
     missing_names.sort();
 
@@ -140,45 +146,80 @@ fn check_output_matches(before_dir: &str, after_dir: &str) -> Result<()> {
 
 #[test]
 fn test_successful_generation() -> Result<()> {
-    ensure_dir_exists("test_temp_success")?;
+    ensure_dir_exists(SUCCESS_TEMP)?;
 
     for name in get_plds("testcases/success")?.iter() {
         std::fs::copy(
             format!("testcases/success/{}", name),
-            format!("test_temp_success/{}", name),
+            format!("{}/{}", SUCCESS_TEMP, name),
         )?;
 
         let results = get_test_bin("galette")
-            .current_dir("test_temp_success")
-            .arg(name)
+            .current_dir(SUCCESS_TEMP)
+            .args(["-m", "-Wnone", name])
             .output()?;
         check_invocation_succeeded(name, results);
     }
 
-    check_output_matches("testcases/success", "test_temp_success")?;
+    check_output_matches("testcases/success", SUCCESS_TEMP)?;
+
+    remove_dir_all(SUCCESS_TEMP)?;
+    Ok(())
+}
+
+// Minimising changes the fuses, but the pins must behave the same, so
+// check the pin and chip files still match galasm's.
+#[test]
+fn test_minimised_generation() -> Result<()> {
+    ensure_dir_exists(MINIMISED_TEMP)?;
+
+    for name in get_plds("testcases/success")?.iter() {
+        std::fs::copy(
+            format!("testcases/success/{}", name),
+            format!("{}/{}", MINIMISED_TEMP, name),
+        )?;
+
+        let results = get_test_bin("galette")
+            .current_dir(MINIMISED_TEMP)
+            .args(["-Wnone", name])
+            .output()?;
+        check_invocation_succeeded(name, results);
+
+        let stem = name.trim_end_matches(".pld");
+        for ext in ["pin", "chp"] {
+            let expected = read_to_string(format!("testcases/success/{}.{}", stem, ext))?;
+            let actual = read_to_string(format!("{}/{}.{}", MINIMISED_TEMP, stem, ext))?;
+            assert_eq!(
+                expected, actual,
+                "'{}.{}' differs when minimised",
+                stem, ext
+            );
+        }
+        assert!(Path::new(&format!("{}/{}.jed", MINIMISED_TEMP, stem)).exists());
+    }
 
-    remove_dir_all("test_temp_success")?;
+    remove_dir_all(MINIMISED_TEMP)?;
     Ok(())
 }
 
 #[test]
 fn test_security_bit() -> Result<()> {
-    ensure_dir_exists("test_temp_security")?;
+    ensure_dir_exists(SECURITY_TEMP)?;
 
     std::fs::copy(
         "testcases/security/security_bit.pld",
-        "test_temp_security/security_bit.pld",
+        format!("{}/security_bit.pld", SECURITY_TEMP),
     )?;
 
     let results = get_test_bin("galette")
-        .current_dir("test_temp_security")
-        .args(["-s", "security_bit.pld"])
+        .current_dir(SECURITY_TEMP)
+        .args(["-m", "-Wnone", "-s", "security_bit.pld"])
         .output()?;
     check_invocation_succeeded("security.pld", results);
 
-    check_output_matches("testcases/security", "test_temp_security")?;
+    check_output_matches("testcases/security", SECURITY_TEMP)?;
 
-    remove_dir_all("test_temp_security")?;
+    remove_dir_all(SECURITY_TEMP)?;
     Ok(())
 }
 
@@ -274,7 +315,7 @@ fn test_failing_generation() -> Result<()> {
     for name in get_plds("testcases/failure")?.iter() {
         let results = get_test_bin("galette")
             .current_dir("testcases/failure")
-            .arg(name)
+            .args(["-m", "-Wnone", name])
             .output()?;
         check_invocation_failed(name, &failure_messages, results);
         failure_messages.remove(name.as_str());